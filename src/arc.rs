@@ -0,0 +1,174 @@
+use std::sync::{Arc, RwLock, Weak};
+
+use html5ever::QualName;
+
+use rcdom::{self, NodeData};
+
+/// ArcHandle is a reference to a node in a tree built by `into_arc_tree`.
+pub type ArcHandle = Arc<ArcNode>;
+
+/// ArcNodeData holds the contents of an `ArcNode`, it mirrors `rcdom::NodeData` with the
+/// tendrils copied into Strings so it can be sent to other threads.
+#[derive(Debug)]
+pub enum ArcNodeData {
+    Document,
+    Doctype {
+        name: String,
+        public_id: String,
+        system_id: String,
+    },
+    Text {
+        contents: RwLock<String>,
+    },
+    Comment {
+        contents: String,
+    },
+    Element {
+        name: QualName,
+        attrs: RwLock<Vec<(QualName, String)>>,
+        /// The contents of a `<template>` element, they are not children of the element.
+        template_contents: Option<ArcHandle>,
+    },
+    ProcessingInstruction {
+        target: String,
+        contents: String,
+    },
+}
+
+/// ArcNode is a node of a dom that is `Send` and `Sync`, so a parsed page can be shared
+/// between threads. Like `rcdom::Node` children are owned by their parent and the parent
+/// is only referenced weakly.
+#[derive(Debug)]
+pub struct ArcNode {
+    pub data: ArcNodeData,
+    pub children: RwLock<Vec<ArcHandle>>,
+    parent: Option<Weak<ArcNode>>,
+}
+
+impl ArcNode {
+    /// parent returns the parent of this node, or None for the root of the tree.
+    pub fn parent(&self) -> Option<ArcHandle> {
+        self.parent.as_ref().and_then(Weak::upgrade)
+    }
+}
+
+fn convert(handle: &rcdom::Handle) -> ArcNodeData {
+    match handle.data {
+        NodeData::Document => ArcNodeData::Document,
+        NodeData::Doctype { ref name, ref public_id, ref system_id } => ArcNodeData::Doctype {
+            name: name.to_string(),
+            public_id: public_id.to_string(),
+            system_id: system_id.to_string(),
+        },
+        NodeData::Text { ref contents } => ArcNodeData::Text {
+            contents: RwLock::new(contents.borrow().to_string()),
+        },
+        NodeData::Comment { ref contents } => ArcNodeData::Comment {
+            contents: contents.to_string(),
+        },
+        NodeData::Element { ref name, ref attrs, ref template_contents, .. } => ArcNodeData::Element {
+            name: name.clone(),
+            attrs: RwLock::new(attrs.borrow().iter()
+                .map(|a| (a.name.clone(), a.value.to_string()))
+                .collect()),
+            // Template contents don't have a parent, so they are converted on their own.
+            // This only recurses once per level of nested templates.
+            template_contents: template_contents.as_ref().map(build),
+        },
+        NodeData::ProcessingInstruction { ref target, ref contents } => {
+            ArcNodeData::ProcessingInstruction {
+                target: target.to_string(),
+                contents: contents.to_string(),
+            }
+        },
+    }
+}
+
+/// build converts the subtree below `root` without recursing, so deep documents can't
+/// overflow the stack.
+fn build(root: &rcdom::Handle) -> ArcHandle {
+    let new_root = Arc::new(ArcNode {
+        data: convert(root),
+        children: RwLock::new(Vec::new()),
+        parent: None,
+    });
+    let mut stack: Vec<(rcdom::Handle, ArcHandle)> = root.children.borrow().iter().rev()
+        .map(|child| (child.clone(), new_root.clone()))
+        .collect();
+    while let Some((handle, parent)) = stack.pop() {
+        let node = Arc::new(ArcNode {
+            data: convert(&handle),
+            children: RwLock::new(Vec::new()),
+            parent: Some(Arc::downgrade(&parent)),
+        });
+        parent.children.write().unwrap().push(node.clone());
+        for child in handle.children.borrow().iter().rev() {
+            stack.push((child.clone(), node.clone()));
+        }
+    }
+    new_root
+}
+
+/// into_arc_tree copies a parsed dom into a tree of `ArcNode`s, which unlike `RcDom` can
+/// be sent to and shared between threads. The whole tree is copied in a single pass and
+/// the returned handle is its document node. The parse errors and quirks mode of the
+/// `RcDom` are not carried over.
+pub fn into_arc_tree(dom: rcdom::RcDom) -> ArcHandle {
+    build(&dom.document)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    fn text(node: &ArcHandle) -> String {
+        let mut text = String::new();
+        let mut stack = vec![node.clone()];
+        while let Some(node) = stack.pop() {
+            if let ArcNodeData::Text { ref contents } = node.data {
+                text.push_str(&contents.read().unwrap());
+            }
+            stack.extend(node.children.read().unwrap().iter().rev().cloned());
+        }
+        text
+    }
+
+    #[test]
+    fn test_into_arc_tree() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<!DOCTYPE html><p class='x'>shared <b>text</b></p><template><i>t</i></template>");
+        let doc = into_arc_tree(dom);
+        assert_send_sync(&doc);
+
+        let worker = doc.clone();
+        let found = thread::spawn(move || text(&worker)).join().unwrap();
+        assert_eq!(found, "shared text");
+
+        let html = doc.children.read().unwrap()[1].clone();
+        assert!(Arc::ptr_eq(&html.parent().unwrap(), &doc));
+        let body = html.children.read().unwrap()[1].clone();
+        let template = body.children.read().unwrap()[1].clone();
+        match template.data {
+            ArcNodeData::Element { ref template_contents, .. } => {
+                assert_eq!(text(template_contents.as_ref().unwrap()), "t");
+            },
+            _ => panic!("expected the template element"),
+        }
+        let p = body.children.read().unwrap()[0].clone();
+        match p.data {
+            ArcNodeData::Element { ref name, ref attrs, .. } => {
+                assert_eq!(&*name.local, "p");
+                assert_eq!(attrs.read().unwrap()[0].1, "x");
+            },
+            _ => panic!("expected the p element"),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_std::io::Read;
+use html5ever::{parse_document, Parser};
+use html5ever::tendril::TendrilSink;
+use html5ever::tendril::stream::Utf8LossyDecoder;
+use html5ever::tree_builder::TreeSink;
+
+use errors::Error;
+use fut::CHUNKS_PER_POLL;
+
+// How much is read from the reader per call, the same default std uses for BufReader.
+const BUF_SIZE: usize = 8 * 1024;
+
+/// ReaderFuture reads an `async_std::io::Read` to its end, feeding everything it reads
+/// into a html5ever parser, and resolves to the finished document. It is a std future, so
+/// it can be awaited on async-std or any other runtime. It is created by `parse_reader`.
+/// Only available with the `async-std` feature.
+#[must_use = "futures do nothing unless polled"]
+pub struct ReaderFuture<R, D>
+    where D: TreeSink,
+{
+    reader: R,
+    // Boxed so the future is Unpin no matter what the sink is.
+    parser: Option<Box<Utf8LossyDecoder<Parser<D>>>>,
+    buf: Vec<u8>,
+}
+
+impl<R, D> Future for ReaderFuture<R, D>
+    where R: Read + Unpin,
+          D: TreeSink,
+{
+    type Output = io::Result<D::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut reads = 0;
+        loop {
+            // Like `ParserFuture`, hand control back to the executor now and then so a
+            // reader that is always ready, like a file, doesn't hold up other tasks.
+            if reads == CHUNKS_PER_POLL {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            let n = match Pin::new(&mut this.reader).poll_read(cx, &mut this.buf) {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(ref err)) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            };
+            if n == 0 {
+                let parser = this.parser.take().expect("polled ReaderFuture after completion");
+                return Poll::Ready(Ok((*parser).finish()));
+            }
+            this.parser.as_mut().expect("polled ReaderFuture after completion")
+                .process(this.buf[..n].into());
+            reads += 1;
+        }
+    }
+}
+
+/// parse_reader parses everything read from an async-std reader, like a `File` or a
+/// `TcpStream`, into `dom`. Read errors are returned as is.
+pub fn parse_reader<R, D>(reader: R, dom: D) -> ReaderFuture<R, D>
+    where R: Read + Unpin,
+          D: TreeSink,
+{
+    ReaderFuture {
+        reader,
+        parser: Some(Box::new(parse_document(dom, Default::default()).from_utf8())),
+        buf: vec![0; BUF_SIZE],
+    }
+}
+
+/// Parse errors turn into `io::Error`s so they can be passed up with `?` from async-std
+/// code. Aborted parses become an error of kind `Other` carrying the reason.
+impl From<Error<io::Error>> for io::Error {
+    fn from(err: Error<io::Error>) -> io::Error {
+        match err {
+            Error::Stream(err) => err,
+            Error::Aborted(abort) => io::Error::other(abort.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Wake, Waker};
+
+    use async_std::task;
+
+    use super::*;
+    use rcdom::RcDom;
+    use errors::Abort;
+
+    #[test]
+    fn test_parse_reader() {
+        let html: &[u8] = b"<html><head><title>test</title></head></html>";
+        let dom = task::block_on(parse_reader(html, RcDom::default())).unwrap();
+        assert_eq!(::canonicalize(&dom), "<html><head><title>test</title></head><body></body></html>");
+
+        let err: io::Error = Error::Aborted(Abort::SniffRejected).into();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    /// Wakes counts how often the future asked to be polled again.
+    struct Wakes(AtomicUsize);
+
+    impl Wake for Wakes {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_yields_between_reads() {
+        let html = "<p>x</p>".repeat(BUF_SIZE * CHUNKS_PER_POLL / 8 * 2);
+        let mut fut = parse_reader(html.as_bytes(), RcDom::default());
+        let wakes = Arc::new(Wakes(AtomicUsize::new(0)));
+        let waker = Waker::from(Arc::clone(&wakes));
+        let mut cx = Context::from_waker(&waker);
+        let dom = loop {
+            if let Poll::Ready(dom) = Pin::new(&mut fut).poll(&mut cx) {
+                break dom.unwrap();
+            }
+        };
+        assert_eq!(::select(&dom, "p").unwrap().len(), BUF_SIZE * CHUNKS_PER_POLL / 8 * 2);
+        assert_eq!(wakes.0.load(Ordering::SeqCst), 2);
+    }
+}
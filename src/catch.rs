@@ -0,0 +1,135 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+
+use futures::{Future, Stream, Poll, Async};
+use html5ever::{parse_document, Parser};
+use html5ever::tendril::TendrilSink;
+use html5ever::tendril::stream::Utf8LossyDecoder;
+use html5ever::tree_builder::TreeSink;
+
+use errors::{Abort, Error};
+use fut::{Budget, ParserFuture};
+
+/// CatchPanicFuture parses a stream like `ParserFuture`, but fails with
+/// `Abort::SinkPanicked` instead of unwinding through `poll` when the sink panics. It is
+/// created by `ParserFuture::catch_sink_panics`.
+#[must_use = "futures do nothing unless polled"]
+pub struct CatchPanicFuture<S, E, D>
+    where D: TreeSink,
+{
+    stream: S,
+    parser: Option<Utf8LossyDecoder<Parser<D>>>,
+    err_type: PhantomData<E>,
+}
+
+/// sink_panicked turns the payload of a panic into an error, keeping the message of panics
+/// raised with `panic!` and friends.
+fn sink_panicked<E>(payload: &(dyn Any + Send)) -> Error<E> {
+    let msg = if let Some(msg) = payload.downcast_ref::<&str>() {
+        (*msg).to_owned()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_owned()
+    };
+    Error::Aborted(Abort::SinkPanicked(msg))
+}
+
+impl<S, C, E, D> Future for CatchPanicFuture<S, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    type Item = D::Output;
+    type Error = Error<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            // Only the parser runs inside catch_unwind, a panicking stream still unwinds.
+            let chunk = match self.stream.poll().map_err(Error::Stream)? {
+                Async::Ready(chunk) => chunk,
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            match chunk {
+                Some(chunk) => {
+                    let result = {
+                        let parser = self.parser.as_mut().expect("polled CatchPanicFuture after completion");
+                        panic::catch_unwind(AssertUnwindSafe(|| parser.process(chunk.as_ref().into())))
+                    };
+                    if let Err(payload) = result {
+                        self.parser = None;
+                        return Err(sink_panicked(&*payload));
+                    }
+                    if budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+                None => {
+                    let parser = self.parser.take().expect("polled CatchPanicFuture after completion");
+                    return match panic::catch_unwind(AssertUnwindSafe(move || parser.finish())) {
+                        Ok(dom) => Ok(Async::Ready(dom)),
+                        Err(payload) => Err(sink_panicked(&*payload)),
+                    };
+                },
+            }
+        }
+    }
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    /// catch_sink_panics creates a `CatchPanicFuture`, which turns a panic in the sink into
+    /// an `Abort::SinkPanicked` error carrying the panic message, for hosting sinks that
+    /// can't be trusted not to panic. Panics propagate as usual from every other parser.
+    ///
+    /// The sink doesn't have to be `UnwindSafe`, most aren't since `RcDom` is built from
+    /// `Rc`s and `RefCell`s. After a panic the parser and the sink are dropped without
+    /// being finished, but anything the sink shares with the outside, like handles into
+    /// the dom or `Rc`s held by its hooks, may have been left half way through an update
+    /// and shouldn't be trusted either. The panic hook still runs, so the panic gets
+    /// printed as usual.
+    pub fn catch_sink_panics(s: S, dom: D) -> CatchPanicFuture<S, E, D> {
+        CatchPanicFuture {
+            stream: s,
+            parser: Some(parse_document(dom, Default::default()).from_utf8()),
+            err_type: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::{self, NodeData, RcDom};
+    use sink::{HookedSink, Hooks};
+
+    struct Boom;
+
+    impl Hooks<rcdom::Handle> for Boom {
+        fn element_created(&mut self, element: &rcdom::Handle) {
+            if let NodeData::Element { ref name, .. } = element.data {
+                assert!(&*name.local != "boom", "found a boom element");
+            }
+        }
+    }
+
+    #[test]
+    fn test_catch_sink_panics() {
+        let parse = |chunks: Vec<&'static str>| {
+            let sink = HookedSink::new(RcDom::default(), Boom);
+            ParserFuture::catch_sink_panics(stream::iter_ok::<_, ()>(chunks), sink).wait()
+        };
+        assert!(parse(vec!["<p>fine</p>"]).is_ok());
+        match parse(vec!["<p>", "<boom>"]) {
+            Err(Error::Aborted(Abort::SinkPanicked(ref msg))) => assert_eq!(msg, "found a boom element"),
+            _ => panic!("expected the panic to be caught"),
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use std::cell::RefCell;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
+
+use futures::{Future, Sink, Stream, Poll, Async};
+use futures::unsync::mpsc;
+use html5ever::{parse_document, Parser};
+use html5ever::tendril::TendrilSink;
+use html5ever::tendril::stream::Utf8LossyDecoder;
+use html5ever::tree_builder::{Tracer, TreeSink};
+
+use common::ancestors;
+use errors::SelectorError;
+use fut::{Budget, ParserFuture};
+use rcdom::{self, Node};
+use select::Selector;
+use serialize::outer_html;
+use sink::{HookedSink, Hooks};
+
+/// Created collects every element the parser creates so they can be checked for
+/// completion once the current chunk has been processed.
+#[derive(Clone, Default)]
+struct Created(Rc<RefCell<Vec<rcdom::Handle>>>);
+
+impl Hooks<rcdom::Handle> for Created {
+    fn element_created(&mut self, element: &rcdom::Handle) {
+        self.0.borrow_mut().push(Rc::clone(element));
+    }
+}
+
+/// Referenced collects every node the tree builder still holds on to, most importantly its
+/// stack of open elements.
+#[derive(Default)]
+struct Referenced(RefCell<HashSet<*const Node>>);
+
+impl Tracer for Referenced {
+    type Handle = rcdom::Handle;
+
+    fn trace_handle(&self, node: &rcdom::Handle) {
+        self.0.borrow_mut().insert(&**node as *const Node);
+    }
+}
+
+/// ClosedElementStream parses a stream of bytes and yields each element as soon as the
+/// parser is done with it, with its complete subtree in place. html5ever doesn't reliably
+/// report when it pops an element off its stack of open elements, so after every chunk
+/// the stream asks the tree builder which elements it still references. Anything no longer
+/// referenced has been closed. Elements closed by the same chunk are yielded in the order
+/// they were completed, children before their parents. Elements the tree builder keeps a
+/// reference to for its own bookkeeping, like `<head>`, are only yielded once the stream
+/// ends.
+/// # Examples
+/// ```rust
+/// extern crate futures;
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use futures::{stream, Future, Stream};
+/// use html5ever::rcdom::{RcDom, NodeData};
+/// use html5ever_stream::ClosedElementStream;
+///
+/// let chunks = vec!["<ul><li>one</li><li>tw", "o</li></ul>"];
+/// let names = ClosedElementStream::new(stream::iter_ok::<_, ()>(chunks), RcDom::default())
+///     .filter_map(|handle| match handle.data {
+///         NodeData::Element { ref name, .. } => Some(name.local.to_string()),
+///         _ => None,
+///     })
+///     .collect()
+///     .wait()
+///     .unwrap();
+/// assert_eq!(names, vec!["li", "li", "ul", "head", "body", "html"]);
+/// ```
+pub struct ClosedElementStream<S, D>
+    where D: TreeSink<Handle=rcdom::Handle>,
+{
+    stream: S,
+    parser: Option<Utf8LossyDecoder<Parser<HookedSink<D, Created>>>>,
+    created: Created,
+    pending: Vec<rcdom::Handle>,
+    closed: VecDeque<rcdom::Handle>,
+}
+
+impl<S, C, E, D> ClosedElementStream<S, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    pub fn new(s: S, dom: D) -> Self {
+        let created = Created::default();
+        let sink = HookedSink::new(dom, created.clone());
+        ClosedElementStream {
+            stream: s,
+            parser: Some(parse_document(sink, Default::default()).from_utf8()),
+            created,
+            pending: Vec::new(),
+            closed: VecDeque::new(),
+        }
+    }
+
+    /// document returns the document node of the dom being built, or None once the
+    /// stream has ended.
+    pub(crate) fn document(&mut self) -> Option<rcdom::Handle> {
+        self.parser.as_mut().map(|parser| parser.inner_sink.tokenizer.sink.sink.get_document())
+    }
+
+    /// batch_done reports if every element closed by the chunks parsed so far has been
+    /// yielded.
+    pub(crate) fn batch_done(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    /// collect moves the elements that are no longer referenced by the tree builder from
+    /// pending to closed, in the order they were completed.
+    fn collect(&mut self) {
+        self.pending.extend(self.created.0.borrow_mut().drain(..));
+        let referenced = Referenced::default();
+        if let Some(ref parser) = self.parser {
+            parser.inner_sink.tokenizer.sink.trace_handles(&referenced);
+        }
+        let referenced = referenced.0.into_inner();
+        let mut done = Vec::new();
+        self.pending.retain(|handle| {
+            if referenced.contains(&(&**handle as *const Node)) {
+                return true;
+            }
+            done.push(Rc::clone(handle));
+            false
+        });
+        // done is in creation order, which puts parents before their children. Replay it
+        // keeping a stack of ancestors so each element comes out after its descendants.
+        let mut open: Vec<rcdom::Handle> = Vec::new();
+        for handle in done {
+            while let Some(top) = open.pop() {
+                if ancestors(&handle).any(|h| Rc::ptr_eq(&h, &top)) {
+                    open.push(top);
+                    break;
+                }
+                self.closed.push_back(top);
+            }
+            open.push(handle);
+        }
+        self.closed.extend(open.into_iter().rev());
+    }
+}
+
+impl<S, C, E, D> Stream for ClosedElementStream<S, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    type Item = rcdom::Handle;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            if let Some(handle) = self.closed.pop_front() {
+                return Ok(Async::Ready(Some(handle)));
+            }
+            let finished = match self.parser {
+                Some(ref mut parser) => match self.stream.poll()? {
+                    Async::Ready(Some(chunk)) => {
+                        parser.process(chunk.as_ref().into());
+                        false
+                    },
+                    Async::Ready(None) => true,
+                    Async::NotReady => return Ok(Async::NotReady),
+                },
+                None => return Ok(Async::Ready(None)),
+            };
+            if finished {
+                // Finishing closes every element that is still open, once the parser is
+                // gone collect treats everything that is pending as closed.
+                if let Some(parser) = self.parser.take() {
+                    parser.finish();
+                }
+            }
+            self.collect();
+            // Chunks that close nothing would otherwise be parsed without ever yielding.
+            if !finished && self.closed.is_empty() && budget.spend() {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+/// SectionStream parses a stream of bytes and yields the serialized html of every element
+/// matching a selector as soon as that element has been fully parsed, so sections of a
+/// page like `<article>`s can be handed off before the rest of the page arrives. Elements
+/// are considered complete once the chunk containing their end has been parsed.
+/// # Examples
+/// ```rust
+/// extern crate futures;
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use futures::{stream, Future, Stream};
+/// use html5ever::rcdom::RcDom;
+/// use html5ever_stream::SectionStream;
+///
+/// let chunks = vec!["<article>one</article><artic", "le>two</article>"];
+/// let sections = SectionStream::new(stream::iter_ok::<_, ()>(chunks), RcDom::default(), "article")
+///     .unwrap()
+///     .collect()
+///     .wait()
+///     .unwrap();
+/// assert_eq!(sections, vec!["<article>one</article>", "<article>two</article>"]);
+/// ```
+pub struct SectionStream<S, D>
+    where D: TreeSink<Handle=rcdom::Handle>,
+{
+    closed: ClosedElementStream<S, D>,
+    selector: Selector,
+}
+
+impl<S, C, E, D> SectionStream<S, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    pub fn new(s: S, dom: D, selector: &str) -> Result<Self, SelectorError> {
+        Ok(SectionStream {
+            closed: ClosedElementStream::new(s, dom),
+            selector: Selector::parse(selector)?,
+        })
+    }
+}
+
+impl<S, C, E, D> Stream for SectionStream<S, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    type Item = String;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.closed.poll()? {
+                Async::Ready(Some(handle)) => if self.selector.matches(&handle) {
+                    return Ok(Async::Ready(Some(outer_html(&handle))));
+                },
+                Async::Ready(None) => return Ok(Async::Ready(None)),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    /// into_node_channel parses a stream like `ClosedElementStream`, sending each element
+    /// into a bounded channel once it is complete. The returned future drives the parse
+    /// and has to be spawned or polled next to whatever reads the receiver. Once `buffer`
+    /// elements are waiting in the channel the future stops reading the input stream until
+    /// the receiver catches up, so a slow consumer slows down the parse instead of letting
+    /// elements pile up. The channel closes when parsing is done. If the receiver is
+    /// dropped the rest of the stream is abandoned and the future resolves right away,
+    /// stream errors fail the future.
+    pub fn into_node_channel(s: S, dom: D, buffer: usize)
+        -> (impl Future<Item=(), Error=E>, mpsc::Receiver<rcdom::Handle>)
+    {
+        let (tx, rx) = mpsc::channel(buffer);
+        let parse = ClosedElementStream::new(s, dom)
+            .map_err(Some)
+            .forward(tx.sink_map_err(|_| None))
+            .map(|_| ())
+            .or_else(|err| match err {
+                Some(err) => Err(err),
+                // The receiver is gone, nobody wants the rest.
+                None => Ok(()),
+            });
+        (parse, rx)
+    }
+
+    /// until_found parses a stream until the first element matching `selector` has been
+    /// closed and resolves to it, dropping the rest of the stream unread. When what's
+    /// needed sits near the top of a page, like its `<title>`, this saves downloading and
+    /// parsing everything after it. Elements are checked as they are closed, see
+    /// `ClosedElementStream`, so a match nested inside another match wins. Resolves to None
+    /// when the stream ends without a match.
+    pub fn until_found(s: S, dom: D, selector: Selector)
+        -> impl Future<Item=Option<rcdom::Handle>, Error=E>
+    {
+        ClosedElementStream::new(s, dom)
+            .filter(move |handle| selector.matches(handle))
+            .into_future()
+            .map(|(found, _)| found)
+            .map_err(|(err, _)| err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future, Stream};
+
+    use super::*;
+    use rcdom::{NodeData, RcDom};
+
+    #[test]
+    fn test_closed_order() {
+        let chunks = vec!["<div><p>a<b>b</b></p><p>c", "</p></div><span>d</span>"];
+        let closed = ClosedElementStream::new(stream::iter_ok::<_, ()>(chunks), RcDom::default());
+        let names: Vec<String> = closed
+            .map(|handle| match handle.data {
+                NodeData::Element { ref name, .. } => name.local.to_string(),
+                _ => panic!("only elements are yielded"),
+            })
+            .collect()
+            .wait()
+            .unwrap();
+        assert_eq!(names, vec!["b", "p", "p", "div", "span", "head", "body", "html"]);
+    }
+
+    #[test]
+    fn test_sections_before_stream_ends() {
+        let chunks = vec![
+            Ok("<html><body><article><p>one"),
+            Ok("</article><div><article>two</article>"),
+            Err(()),
+        ];
+        let sections = SectionStream::new(stream::iter_result(chunks), RcDom::default(), "article")
+            .unwrap();
+        let (first, rest) = sections.into_future().wait().map_err(|_| ()).unwrap();
+        assert_eq!(first.unwrap(), "<article><p>one</p></article>");
+        let (second, rest) = rest.into_future().wait().map_err(|_| ()).unwrap();
+        assert_eq!(second.unwrap(), "<article>two</article>");
+        assert!(rest.into_future().wait().is_err());
+    }
+
+    #[test]
+    fn test_into_node_channel() {
+        let chunks = vec!["<p>a</p><p>b", "</p>"];
+        let (parse, rx) = ParserFuture::into_node_channel(stream::iter_ok::<_, ()>(chunks),
+                                                          RcDom::default(), 1);
+        let (_, handles) = parse.join(rx.collect().map_err(|_| ())).wait().unwrap();
+        let names: Vec<String> = handles.iter().map(outer_html).collect();
+        assert_eq!(names[..2], ["<p>a</p>".to_owned(), "<p>b</p>".to_owned()]);
+        assert_eq!(handles.len(), 5);
+
+        let chunks = vec![Ok("<p>a</p>"), Err(())];
+        let (parse, rx) = ParserFuture::into_node_channel(stream::iter_result(chunks),
+                                                          RcDom::default(), 1);
+        drop(rx);
+        assert!(parse.wait().is_ok());
+    }
+
+    #[test]
+    fn test_until_found() {
+        let chunks = vec![Ok("<title>first</title><p>"), Err(())];
+        let selector = Selector::parse("title").unwrap();
+        let found = ParserFuture::until_found(stream::iter_result(chunks), RcDom::default(), selector)
+            .wait()
+            .unwrap();
+        assert_eq!(outer_html(&found.unwrap()), "<title>first</title>");
+
+        let selector = Selector::parse("video").unwrap();
+        let chunks = vec!["<p>no match</p>"];
+        let found = ParserFuture::until_found(stream::iter_ok::<_, ()>(chunks), RcDom::default(), selector);
+        assert!(found.wait().unwrap().is_none());
+    }
+}
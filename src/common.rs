@@ -2,34 +2,250 @@ use std::collections::VecDeque;
 use std::rc::Rc;
 
 use futures::{Stream, Poll, Async};
-use html5ever::rcdom;
+use html5ever::LocalName;
+use html5ever::tendril::StrTendril;
 
+use rcdom::{self, NodeData};
+
+/// Predicate decides if a node should be yielded by a filtered traversal.
+pub(crate) type Predicate = Box<dyn Fn(&rcdom::Handle) -> bool>;
+
+/// NodeTraverser is the breadth first traversal that powers `NodeStream` and `NodeIter`.
+/// It is exposed so custom stream or iterator adapters can be built on top of it, it
+/// yields each node of the document exactly once, starting at the document node itself.
+/// The contents of `<template>` elements live in a separate fragment and are not
+/// traversed unless `including_templates` is used.
 pub struct NodeTraverser {
     queue: VecDeque<rcdom::Handle>,
+    // Documents still waiting to be traversed, see `over_many`.
+    roots: VecDeque<rcdom::Handle>,
+    // Nodes failing the filter are still descended into, they just aren't yielded.
+    filter: Option<Predicate>,
+    templates: bool,
 }
 
 impl NodeTraverser {
-    fn new(dom: &rcdom::RcDom) -> Self {
+    pub fn new(dom: &rcdom::RcDom) -> Self {
         let mut queue = VecDeque::new();
         queue.push_back(Rc::clone(&dom.document));
         NodeTraverser{
             queue: queue,
+            roots: VecDeque::new(),
+            filter: None,
+            templates: false,
+        }
+    }
+
+    /// over_many traverses each of the given documents in turn. Every document is walked
+    /// to the end before the next one starts, so nodes of different documents never
+    /// interleave.
+    pub fn over_many(doms: &[rcdom::RcDom]) -> Self {
+        NodeTraverser {
+            queue: VecDeque::new(),
+            roots: doms.iter().map(|dom| Rc::clone(&dom.document)).collect(),
+            filter: None,
+            templates: false,
         }
     }
 
-    fn next(&mut self) -> Option<rcdom::Handle> {
-        match self.queue.pop_front() {
-            Some(ref handle) => {
-                for child in handle.children.borrow().iter() {
-                    self.queue.push_back(Rc::clone(child));
+    /// filtered makes the traversal skip the nodes `filter` rejects.
+    pub(crate) fn filtered(mut self, filter: Predicate) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// including_templates makes the traversal descend into the contents of `<template>`
+    /// elements. The fragment holding the contents is yielded right after the template's
+    /// own children, followed by the nodes inside it. Templates inside template contents
+    /// are descended into as well, at any depth.
+    pub fn including_templates(mut self) -> Self {
+        self.templates = true;
+        self
+    }
+}
+
+impl Iterator for NodeTraverser {
+    type Item = rcdom::Handle;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.queue.is_empty() {
+                self.queue.extend(self.roots.pop_front());
+            }
+            let handle = self.queue.pop_front()?;
+            for child in handle.children.borrow().iter() {
+                self.queue.push_back(Rc::clone(child));
+            }
+            if self.templates {
+                if let NodeData::Element { template_contents: Some(ref contents), .. } = handle.data {
+                    self.queue.push_back(Rc::clone(contents));
                 }
-                Some(Rc::clone(handle))
+            }
+            match self.filter {
+                Some(ref filter) if !filter(&handle) => {},
+                _ => return Some(handle),
+            }
+        }
+    }
+}
+
+/// parent follows the weak parent reference of the given node. It returns None for nodes
+/// without a parent, or whose parent has already been dropped.
+pub(crate) fn parent(handle: &rcdom::Handle) -> Option<rcdom::Handle> {
+    let weak = handle.parent.take()?;
+    let parent = weak.upgrade();
+    handle.parent.set(Some(weak));
+    parent
+}
+
+/// tag_name returns the local name of an element, or None for any other kind of node.
+pub fn tag_name(handle: &rcdom::Handle) -> Option<LocalName> {
+    match handle.data {
+        NodeData::Element { ref name, .. } => Some(name.local.clone()),
+        _ => None,
+    }
+}
+
+/// is_element checks if the node is an element, of any namespace.
+pub fn is_element(handle: &rcdom::Handle) -> bool {
+    matches!(handle.data, NodeData::Element { .. })
+}
+
+/// is_html checks if the node is an html namespaced element with the given local name.
+pub(crate) fn is_html(handle: &rcdom::Handle, local: &LocalName) -> bool {
+    match handle.data {
+        NodeData::Element { ref name, .. } => name.ns == ns!(html) && name.local == *local,
+        _ => false,
+    }
+}
+
+/// child_element returns the first child of `handle` that is an html element with the
+/// given local name.
+pub(crate) fn child_element(handle: &rcdom::Handle, local: &LocalName) -> Option<rcdom::Handle> {
+    handle.children.borrow().iter().find(|h| is_html(h, local)).cloned()
+}
+
+/// root_element returns the document element, the first element child of the document
+/// node. `dom.document`, where traversals start, is the `Document` node above it, which
+/// also holds the doctype and any comments outside of `<html>`. Parsing html always
+/// creates an `<html>` root, trees built by other sinks can have a different one or
+/// none at all.
+pub fn root_element(dom: &rcdom::RcDom) -> Option<rcdom::Handle> {
+    dom.document.children.borrow().iter().find(|h| is_element(h)).cloned()
+}
+
+/// html_element returns the root element if it is an html `<html>` element.
+fn html_element(dom: &rcdom::RcDom) -> Option<rcdom::Handle> {
+    root_element(dom).filter(|h| is_html(h, &local_name!("html")))
+}
+
+/// body returns the `<body>` element of a parsed document. html5ever always puts it right
+/// below the `<html>` element, unless the document uses a `<frameset>` instead.
+pub(crate) fn body(dom: &rcdom::RcDom) -> Option<rcdom::Handle> {
+    html_element(dom).and_then(|html| child_element(&html, &local_name!("body")))
+}
+
+/// head_and_body returns the `<head>` and `<body>` elements of a parsed document, looking
+/// through the children of the `<html>` element only once. Either is None when missing,
+/// a document using a `<frameset>` has no body.
+pub fn head_and_body(dom: &rcdom::RcDom) -> (Option<rcdom::Handle>, Option<rcdom::Handle>) {
+    let html = match html_element(dom) {
+        Some(html) => html,
+        None => return (None, None),
+    };
+    let (mut head, mut body) = (None, None);
+    for child in html.children.borrow().iter() {
+        if head.is_none() && is_html(child, &local_name!("head")) {
+            head = Some(Rc::clone(child));
+        } else if body.is_none() && is_html(child, &local_name!("body")) {
+            body = Some(Rc::clone(child));
+        }
+    }
+    (head, body)
+}
+
+/// Ancestors walks up the tree from a node by following parent references. It is created
+/// by `ancestors`.
+pub struct Ancestors {
+    current: Option<rcdom::Handle>,
+}
+
+impl Iterator for Ancestors {
+    type Item = rcdom::Handle;
+    fn next(&mut self) -> Option<Self::Item> {
+        let parent = parent(self.current.as_ref()?);
+        self.current = parent.clone();
+        parent
+    }
+}
+
+/// ancestors yields the parent of the given node, then its parent, and so on up to and
+/// including the document node. Iteration stops early if a parent has been dropped, which
+/// can happen for nodes that outlive the rest of their DOM.
+pub fn ancestors(handle: &rcdom::Handle) -> Ancestors {
+    Ancestors {
+        current: Some(Rc::clone(handle)),
+    }
+}
+
+/// Siblings walks over the siblings of a node in either direction, it is created by
+/// `next_siblings` and `prev_siblings`. Nodes don't know their own index, so creating one
+/// looks the node up in its parent's children once. After that it keeps the index and
+/// every step is constant time, walk a million siblings with a single `Siblings` rather
+/// than one per node.
+pub struct Siblings {
+    parent: Option<rcdom::Handle>,
+    index: usize,
+    forward: bool,
+}
+
+impl Siblings {
+    fn new(handle: &rcdom::Handle, forward: bool) -> Self {
+        let parent = parent(handle);
+        let index = parent.as_ref()
+            .and_then(|p| p.children.borrow().iter().position(|c| Rc::ptr_eq(c, handle)));
+        match index {
+            Some(index) => Siblings {
+                parent,
+                index: if forward { index + 1 } else { index },
+                forward,
+            },
+            None => Siblings {
+                parent: None,
+                index: 0,
+                forward,
             },
-            None => None,
         }
     }
 }
 
+impl Iterator for Siblings {
+    type Item = rcdom::Handle;
+    fn next(&mut self) -> Option<Self::Item> {
+        let parent = self.parent.as_ref()?;
+        if self.forward {
+            self.index += 1;
+            parent.children.borrow().get(self.index - 1).cloned()
+        } else if self.index > 0 {
+            self.index -= 1;
+            parent.children.borrow().get(self.index).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+/// next_siblings yields the siblings that follow the given node, nearest first. Nodes
+/// without a parent have no siblings.
+pub fn next_siblings(handle: &rcdom::Handle) -> Siblings {
+    Siblings::new(handle, true)
+}
+
+/// prev_siblings yields the siblings that precede the given node, nearest first. Nodes
+/// without a parent have no siblings.
+pub fn prev_siblings(handle: &rcdom::Handle) -> Siblings {
+    Siblings::new(handle, false)
+}
+
 /// NodeStream uses a VecDeque to fully traverse the given RcDom and emit reference
 /// counted handles to each node as a `futures::Stream`. Pretty sure this won't leak
 /// memory since everything is either owned by a NodeStream struct or Rc'd.
@@ -65,6 +281,68 @@ impl NodeStream {
     pub fn new(dom: &rcdom::RcDom) -> Self {
         NodeStream(NodeTraverser::new(dom))
     }
+
+    /// over_many streams the nodes of several documents one after the other, like chaining
+    /// a `NodeStream` for each of them.
+    pub fn over_many(doms: &[rcdom::RcDom]) -> Self {
+        NodeStream(NodeTraverser::over_many(doms))
+    }
+
+    /// skip_doctype streams the nodes of the document like `new`, leaving out its doctype.
+    pub fn skip_doctype(dom: &rcdom::RcDom) -> Self {
+        let filter = |handle: &rcdom::Handle| !matches!(handle.data, NodeData::Doctype { .. });
+        NodeStream(NodeTraverser::new(dom).filtered(Box::new(filter)))
+    }
+
+    /// skip_whitespace streams the nodes of the document like `new`, leaving out text nodes
+    /// made up of nothing but whitespace, like the indentation between elements. Text nodes
+    /// with anything else in them are yielded whole, surrounding whitespace included.
+    pub fn skip_whitespace(dom: &rcdom::RcDom) -> Self {
+        let filter = |handle: &rcdom::Handle| match handle.data {
+            NodeData::Text { ref contents } => !contents.borrow().chars().all(char::is_whitespace),
+            _ => true,
+        };
+        NodeStream(NodeTraverser::new(dom).filtered(Box::new(filter)))
+    }
+
+    /// including_templates streams the nodes of the document like `new`, and also the
+    /// contents of its `<template>` elements, which `new` leaves out. The contents of
+    /// templates nested inside other templates are followed too, however deep they go.
+    pub fn including_templates(dom: &rcdom::RcDom) -> Self {
+        NodeStream(NodeTraverser::new(dom).including_templates())
+    }
+
+    /// visit calls `f` with every node of the document, in the same order a `NodeStream`
+    /// yields them, without wrapping the traversal in a stream. Nodes are passed by
+    /// reference, only those with children of their own are cloned to come back to them
+    /// later, so the text nodes and other leaves that make up most of a page cost no
+    /// refcount updates at all. The children of the node's parent are borrowed while `f`
+    /// runs, so `f` can look at the tree but must not change it.
+    pub fn visit<F>(dom: &rcdom::RcDom, mut f: F)
+        where F: FnMut(&rcdom::Handle),
+    {
+        f(&dom.document);
+        let mut queue = VecDeque::new();
+        queue.push_back(Rc::clone(&dom.document));
+        while let Some(parent) = queue.pop_front() {
+            for child in parent.children.borrow().iter() {
+                f(child);
+                if !child.children.borrow().is_empty() {
+                    queue.push_back(Rc::clone(child));
+                }
+            }
+        }
+    }
+
+    pub(crate) fn from_traverser(traverser: NodeTraverser) -> Self {
+        NodeStream(traverser)
+    }
+
+    /// into_vec collects the remaining nodes into a Vec right away. The traversal never
+    /// waits on anything, so there is no need to go through `collect` and `wait`.
+    pub fn into_vec(self) -> Vec<rcdom::Handle> {
+        self.0.collect()
+    }
 }
 
 impl Stream for NodeStream {
@@ -82,6 +360,30 @@ impl NodeIter {
     pub fn new(dom: &rcdom::RcDom) -> Self {
         NodeIter(NodeTraverser::new(dom))
     }
+
+    /// into_vec collects the remaining nodes into a Vec, in the same order as `NodeStream`.
+    pub fn into_vec(self) -> Vec<rcdom::Handle> {
+        self.0.collect()
+    }
+
+    /// text_tendrils yields the contents of every text node in document order as a
+    /// `StrTendril`, without copying them into Strings. The tendrils share their buffers
+    /// with the DOM, so holding on to one keeps the buffer it points into alive even after
+    /// the DOM is dropped, and the first mutation of a tendril copies it.
+    pub fn text_tendrils(dom: &rcdom::RcDom) -> TextTendrils {
+        TextTendrils(EventIter::new(dom))
+    }
+
+    /// text_with_context yields every text node in document order like `text_tendrils`,
+    /// paired with the local name of the element it is directly inside of, so text in an
+    /// `<h1>` can be told apart from text in a `<p>`. Text with no element around it gets
+    /// an empty name.
+    pub fn text_with_context(dom: &rcdom::RcDom) -> TextWithContext {
+        TextWithContext {
+            events: EventIter::new(dom),
+            open: Vec::new(),
+        }
+    }
 }
 
 impl Iterator for NodeIter {
@@ -90,3 +392,376 @@ impl Iterator for NodeIter {
         self.0.next()
     }
 }
+
+/// TextTendrils yields the text of a document as shared `StrTendril`s, it is created by
+/// `NodeIter::text_tendrils`.
+pub struct TextTendrils(EventIter);
+
+impl Iterator for TextTendrils {
+    type Item = StrTendril;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let DomEvent::Text(contents) = self.0.next()? {
+                return Some(contents);
+            }
+        }
+    }
+}
+
+/// TextWithContext yields the text of a document along with the name of the element each
+/// piece of text is in, it is created by `NodeIter::text_with_context`.
+pub struct TextWithContext {
+    events: EventIter,
+    // Names of the elements entered so far and not yet left.
+    open: Vec<LocalName>,
+}
+
+impl Iterator for TextWithContext {
+    type Item = (LocalName, StrTendril);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.events.next()? {
+                DomEvent::Open(handle) => {
+                    self.open.push(tag_name(&handle).expect("only elements are opened"));
+                },
+                DomEvent::Close(_) => {
+                    self.open.pop();
+                },
+                DomEvent::Text(contents) => {
+                    let tag = self.open.last().cloned().unwrap_or_else(|| LocalName::from(""));
+                    return Some((tag, contents));
+                },
+            }
+        }
+    }
+}
+
+/// DomEvent is emitted by `EventIter` while replaying a finished DOM in document order.
+pub enum DomEvent {
+    /// Entering an element, emitted before any of its children.
+    Open(rcdom::Handle),
+    /// Leaving an element, emitted after all of its children.
+    Close(rcdom::Handle),
+    /// The contents of a text node.
+    Text(StrTendril),
+}
+
+/// elements yields every element in the subtree rooted at the given node, in document
+/// order.
+pub(crate) fn elements(handle: &rcdom::Handle) -> impl Iterator<Item=rcdom::Handle> {
+    EventIter::from_handle(handle).filter_map(|event| match event {
+        DomEvent::Open(handle) => Some(handle),
+        _ => None,
+    })
+}
+
+/// descendants_by_tag yields the elements below `handle` with the given tag name, in
+/// document order. It is `get_elements_by_tag_name` scoped to one subtree, handy once a
+/// container has been picked out. The name is matched ignoring ascii case, in any
+/// namespace, and `handle` itself is never yielded.
+pub fn descendants_by_tag(handle: &rcdom::Handle, local: &str) -> impl Iterator<Item=rcdom::Handle> {
+    let root = Rc::clone(handle);
+    let local = local.to_owned();
+    elements(handle).filter(move |h| !Rc::ptr_eq(h, &root) && match h.data {
+        NodeData::Element { ref name, .. } => name.local.as_ref().eq_ignore_ascii_case(&local),
+        _ => false,
+    })
+}
+
+/// Step is a single entry on the explicit stack used by the depth first traversals in
+/// this crate. A node is entered, its children are visited and then it is exited.
+pub(crate) enum Step {
+    Enter(rcdom::Handle),
+    Exit(rcdom::Handle),
+}
+
+/// EventIter walks an already built RcDom depth first and emits a `DomEvent` when it
+/// enters an element, leaves an element, or passes over a text node. Comments, doctypes
+/// and processing instructions are skipped. It keeps an explicit stack instead of
+/// recursing, so it is safe to use on arbitrarily deep documents.
+/// # Examples
+/// ```rust
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use html5ever::parse_document;
+/// use html5ever::rcdom::{RcDom, NodeData};
+/// use html5ever::tendril::TendrilSink;
+/// use html5ever_stream::{EventIter, DomEvent};
+///
+/// const TEST_HTML: &'static str = "<html> <head> <title> test </title> </head> </html>";
+/// let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+/// let mut depth = 0;
+/// for event in EventIter::new(&dom) {
+///     match event {
+///         DomEvent::Open(handle) => {
+///             if let NodeData::Element { ref name, .. } = handle.data {
+///                 println!("{:width$}<{}>", "", name.local, width = depth * 2);
+///             }
+///             depth += 1;
+///         },
+///         DomEvent::Close(_) => depth -= 1,
+///         DomEvent::Text(_) => {},
+///     }
+/// }
+/// ```
+pub struct EventIter {
+    stack: Vec<Step>,
+}
+
+impl EventIter {
+    pub fn new(dom: &rcdom::RcDom) -> Self {
+        EventIter::from_handle(&dom.document)
+    }
+
+    /// from_handle replays only the subtree rooted at the given node.
+    pub fn from_handle(handle: &rcdom::Handle) -> Self {
+        EventIter{
+            stack: vec![Step::Enter(Rc::clone(handle))],
+        }
+    }
+
+    fn push_children(&mut self, handle: &rcdom::Handle) {
+        for child in handle.children.borrow().iter().rev() {
+            self.stack.push(Step::Enter(Rc::clone(child)));
+        }
+    }
+}
+
+impl Iterator for EventIter {
+    type Item = DomEvent;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let handle = match self.stack.pop()? {
+                Step::Exit(handle) => return Some(DomEvent::Close(handle)),
+                Step::Enter(handle) => handle,
+            };
+            match handle.data {
+                NodeData::Element { .. } => {
+                    self.stack.push(Step::Exit(Rc::clone(&handle)));
+                    self.push_children(&handle);
+                    return Some(DomEvent::Open(handle));
+                },
+                NodeData::Text { ref contents } => {
+                    return Some(DomEvent::Text(contents.borrow().clone()));
+                },
+                NodeData::Document => self.push_children(&handle),
+                _ => {},
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+    use super::*;
+    use rcdom::{RcDom, NodeData};
+
+    const TEST_HTML: &'static str = "<html><head><title>test</title></head><body><p>a<b>b</b></p></body></html>";
+
+    fn name(handle: &rcdom::Handle) -> String {
+        match handle.data {
+            NodeData::Element { ref name, .. } => name.local.to_string(),
+            _ => panic!("not an element"),
+        }
+    }
+
+    #[test]
+    fn test_event_iter_order() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let events: Vec<String> = EventIter::new(&dom).map(|e| match e {
+            DomEvent::Open(ref h) => format!("<{}>", name(h)),
+            DomEvent::Close(ref h) => format!("</{}>", name(h)),
+            DomEvent::Text(ref t) => t.to_string(),
+        }).collect();
+        assert_eq!(events.concat(), TEST_HTML);
+    }
+
+    #[test]
+    fn test_text_tendrils() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let text: Vec<StrTendril> = NodeIter::text_tendrils(&dom).collect();
+        assert_eq!(text, vec![StrTendril::from("test"), "a".into(), "b".into()]);
+    }
+
+    #[test]
+    fn test_into_vec() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let nodes = NodeStream::new(&dom).into_vec();
+        assert_eq!(nodes.len(), 10);
+        assert!(Rc::ptr_eq(&nodes[0], &dom.document));
+        let mut iter = NodeIter::new(&dom);
+        iter.next();
+        assert_eq!(iter.into_vec().len(), nodes.len() - 1);
+    }
+
+    #[test]
+    fn test_tag_name() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let names: Vec<LocalName> = NodeIter::new(&dom).filter_map(|h| tag_name(&h)).collect();
+        assert_eq!(names, vec![local_name!("html"), local_name!("head"), local_name!("body"),
+                               local_name!("title"), local_name!("p"), local_name!("b")]);
+        assert!(!is_element(&dom.document));
+        assert_eq!(NodeIter::new(&dom).filter(is_element).count(), names.len());
+    }
+
+    #[test]
+    fn test_head_and_body() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let (head, body) = head_and_body(&dom);
+        assert_eq!(name(&head.unwrap()), "head");
+        assert_eq!(name(&body.unwrap()), "body");
+        let frames = parse_document(RcDom::default(), Default::default())
+            .one("<frameset><frame></frameset>");
+        let (head, body) = head_and_body(&frames);
+        assert!(head.is_some());
+        assert!(body.is_none());
+    }
+
+    #[test]
+    fn test_skip_doctype() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<!DOCTYPE html><p>a</p>");
+        let is_doctype = |h: &rcdom::Handle| matches!(h.data, NodeData::Doctype { .. });
+        assert_eq!(NodeStream::new(&dom).into_vec().iter().filter(|h| is_doctype(h)).count(), 1);
+        let nodes = NodeStream::skip_doctype(&dom).into_vec();
+        assert!(!nodes.iter().any(is_doctype));
+        assert_eq!(nodes.len(), NodeStream::new(&dom).into_vec().len() - 1);
+    }
+
+    #[test]
+    fn test_text_with_context() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<h1>title</h1><p>body <b>bold</b> more</p>");
+        let texts: Vec<(String, String)> = NodeIter::text_with_context(&dom)
+            .map(|(tag, text)| (tag.to_string(), text.to_string()))
+            .collect();
+        assert_eq!(texts, vec![
+            ("h1".to_owned(), "title".to_owned()),
+            ("p".to_owned(), "body ".to_owned()),
+            ("b".to_owned(), "bold".to_owned()),
+            ("p".to_owned(), " more".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_visit() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let mut visited = Vec::new();
+        NodeStream::visit(&dom, |handle| visited.push(Rc::clone(handle)));
+        let streamed = NodeStream::new(&dom).into_vec();
+        assert_eq!(visited.len(), streamed.len());
+        assert!(visited.iter().zip(&streamed).all(|(a, b)| Rc::ptr_eq(a, b)));
+    }
+
+    #[test]
+    fn test_including_templates() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<template><p>hidden</p></template>");
+        let is_p = |h: &rcdom::Handle| tag_name(h) == Some(local_name!("p"));
+        assert!(!NodeStream::new(&dom).into_vec().iter().any(is_p));
+        assert_eq!(NodeStream::including_templates(&dom).into_vec().iter().filter(|h| is_p(h)).count(), 1);
+    }
+
+    #[test]
+    fn test_descendants_by_tag() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<p>0</p><div><p>1</p><span><P>2</P></span><div><p>3</p></div></div>");
+        let div = descendants_by_tag(&dom.document, "DIV").next().unwrap();
+        let found: Vec<String> = descendants_by_tag(&div, "p").map(|h| ::text_content(&h)).collect();
+        assert_eq!(found, vec!["1", "2", "3"]);
+        assert_eq!(descendants_by_tag(&div, "div").count(), 1);
+    }
+
+    // Anything keeping track of sibling indices has to count along the children instead of
+    // looking each node up in its parent, which is quadratic for a parent this wide.
+    #[test]
+    fn test_million_siblings() {
+        let dom = parse_document(RcDom::default(), Default::default()).one("<br>".repeat(1_000_000));
+        assert_eq!(NodeTraverser::new(&dom).count(), 1_000_004);
+        let body = body(&dom).unwrap();
+        let first = body.children.borrow()[0].clone();
+        let last = body.children.borrow()[999_999].clone();
+        assert_eq!(next_siblings(&first).count(), 999_999);
+        assert_eq!(prev_siblings(&last).count(), 999_999);
+    }
+
+    #[test]
+    fn test_skip_whitespace() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<ul>\n  <li> a </li>\n  <li>\t</li>\n</ul>");
+        let texts = |nodes: Vec<rcdom::Handle>| -> Vec<String> {
+            nodes.iter().filter_map(|h| match h.data {
+                NodeData::Text { ref contents } => Some(contents.borrow().to_string()),
+                _ => None,
+            }).collect()
+        };
+        assert_eq!(texts(NodeStream::skip_whitespace(&dom).into_vec()), vec![" a "]);
+        assert_eq!(texts(NodeStream::new(&dom).into_vec()).len(), 5);
+    }
+
+    #[test]
+    fn test_root_element() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<!DOCTYPE html><!-- c --><p>x</p>");
+        let root = root_element(&dom).unwrap();
+        assert_eq!(name(&root), "html");
+        assert!(!Rc::ptr_eq(&root, &dom.document));
+        assert!(root_element(&RcDom::default()).is_none());
+    }
+
+    #[test]
+    fn test_nested_templates() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<template><p>1</p><template><p>2</p><template><p>3</p></template></template></template>");
+        let is_p = |h: &&rcdom::Handle| tag_name(h) == Some(local_name!("p"));
+        assert_eq!(NodeStream::including_templates(&dom).into_vec().iter().filter(is_p).count(), 3);
+    }
+
+    #[test]
+    fn test_over_many() {
+        let doms = vec![
+            parse_document(RcDom::default(), Default::default()).one(TEST_HTML),
+            parse_document(RcDom::default(), Default::default()).one("<p>second</p>"),
+        ];
+        let nodes = NodeStream::over_many(&doms).into_vec();
+        assert_eq!(nodes.len(), 10 + 6);
+        assert!(Rc::ptr_eq(&nodes[0], &doms[0].document));
+        assert!(Rc::ptr_eq(&nodes[10], &doms[1].document));
+        assert!(NodeStream::over_many(&[]).into_vec().is_empty());
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        let bold = NodeIter::new(&dom).find(|h| match h.data {
+            NodeData::Element { ref name, .. } => &*name.local == "b",
+            _ => false,
+        }).unwrap();
+        let names: Vec<String> = ancestors(&bold)
+            .take_while(|h| matches!(h.data, NodeData::Element { .. }))
+            .map(|h| name(&h))
+            .collect();
+        assert_eq!(names, vec!["p", "body", "html"]);
+        assert!(Rc::ptr_eq(&ancestors(&bold).last().unwrap(), &dom.document));
+        assert_eq!(ancestors(&dom.document).count(), 0);
+    }
+
+    #[test]
+    fn test_siblings() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<ul><li>1</li><li>2</li><li>3</li><li>4</li></ul>");
+        let items: Vec<rcdom::Handle> = NodeIter::new(&dom).filter(|h| match h.data {
+            NodeData::Element { ref name, .. } => &*name.local == "li",
+            _ => false,
+        }).collect();
+        let text = |h: rcdom::Handle| ::text_content(&h);
+        assert_eq!(next_siblings(&items[1]).map(&text).collect::<Vec<_>>(), vec!["3", "4"]);
+        assert_eq!(prev_siblings(&items[2]).map(&text).collect::<Vec<_>>(), vec!["2", "1"]);
+        assert_eq!(prev_siblings(&items[0]).count(), 0);
+        assert_eq!(next_siblings(&items[3]).count(), 0);
+        assert_eq!(next_siblings(&dom.document).count(), 0);
+    }
+}
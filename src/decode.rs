@@ -0,0 +1,144 @@
+use std::mem;
+use std::str;
+
+use futures::{Stream, Poll, Async};
+use html5ever::tree_builder::TreeSink;
+
+use fut::{ParserFuture, StrParserFuture};
+
+/// ReplacementPolicy says what to do with bytes that aren't valid utf-8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplacementPolicy {
+    /// Replace each invalid sequence with U+FFFD, like `ParserFuture::new` does.
+    Default,
+    /// Replace each invalid sequence with the given character.
+    Char(char),
+    /// Drop invalid sequences entirely.
+    Drop,
+}
+
+impl ReplacementPolicy {
+    fn replace(&self, out: &mut String) {
+        match *self {
+            ReplacementPolicy::Default => out.push('\u{fffd}'),
+            ReplacementPolicy::Char(c) => out.push(c),
+            ReplacementPolicy::Drop => {},
+        }
+    }
+}
+
+/// Decode turns a stream of bytes into a stream of Strings, replacing invalid utf-8
+/// according to a `ReplacementPolicy`. Characters split across chunks are put back
+/// together before being decoded.
+pub struct Decode<S> {
+    stream: S,
+    policy: ReplacementPolicy,
+    // The start of a character that was cut off at the end of the last chunk.
+    incomplete: Vec<u8>,
+}
+
+impl<S> Decode<S> {
+    pub fn new(stream: S, policy: ReplacementPolicy) -> Self {
+        Decode {
+            stream,
+            policy,
+            incomplete: Vec::new(),
+        }
+    }
+
+    fn decode(&mut self, chunk: &[u8]) -> String {
+        let joined;
+        let mut input = if self.incomplete.is_empty() {
+            chunk
+        } else {
+            let mut buf = mem::take(&mut self.incomplete);
+            buf.extend_from_slice(chunk);
+            joined = buf;
+            &joined[..]
+        };
+        let mut out = String::with_capacity(input.len());
+        loop {
+            match str::from_utf8(input) {
+                Ok(valid) => {
+                    out.push_str(valid);
+                    return out;
+                },
+                Err(e) => {
+                    let (valid, rest) = input.split_at(e.valid_up_to());
+                    out.push_str(str::from_utf8(valid).unwrap());
+                    match e.error_len() {
+                        Some(len) => {
+                            self.policy.replace(&mut out);
+                            input = &rest[len..];
+                        },
+                        None => {
+                            self.incomplete.extend_from_slice(rest);
+                            return out;
+                        },
+                    }
+                },
+            }
+        }
+    }
+}
+
+impl<S, C> Stream for Decode<S>
+    where S: Stream<Item=C>,
+          C: AsRef<[u8]>,
+{
+    type Item = String;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.stream.poll()? {
+            Async::Ready(Some(chunk)) => Ok(Async::Ready(Some(self.decode(chunk.as_ref())))),
+            Async::Ready(None) if !self.incomplete.is_empty() => {
+                // The stream ended in the middle of a character.
+                self.incomplete.clear();
+                let mut out = String::new();
+                self.policy.replace(&mut out);
+                Ok(Async::Ready(Some(out)))
+            },
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    /// with_replacement creates a future that parses a stream of bytes like
+    /// `ParserFuture::new`, but lets `policy` decide what invalid utf-8 turns into.
+    pub fn with_replacement(s: S, dom: D, policy: ReplacementPolicy)
+        -> StrParserFuture<Decode<S>, String, E, D>
+    {
+        ParserFuture::new_str(Decode::new(s, policy), dom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::RcDom;
+    use text::text_content;
+
+    fn parse(policy: ReplacementPolicy) -> String {
+        // The e with an acute accent is split across chunks, the other bytes are invalid.
+        let chunks: Vec<&'static [u8]> = vec![b"<p>caf\xc3", b"\xa9 \xff bad\xe2\x82"];
+        let s = stream::iter_ok::<_, ()>(chunks);
+        let dom = ParserFuture::with_replacement(s, RcDom::default(), policy).wait().unwrap();
+        text_content(&dom.document)
+    }
+
+    #[test]
+    fn test_replacement_policies() {
+        assert_eq!(parse(ReplacementPolicy::Default), "caf\u{e9} \u{fffd} bad\u{fffd}");
+        assert_eq!(parse(ReplacementPolicy::Char(' ')), "caf\u{e9}   bad ");
+        assert_eq!(parse(ReplacementPolicy::Drop), "caf\u{e9}  bad");
+    }
+}
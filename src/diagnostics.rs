@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use futures::{Future, Stream, Poll, Async};
+use html5ever::ParseOpts;
+use html5ever::tokenizer::TokenizerOpts;
+use html5ever::tree_builder::TreeBuilderOpts;
+
+use fut::ParserFuture;
+use rcdom::{self, RcDom};
+use sink::{HookedSink, Hooks};
+
+/// Diagnostic is a single parse error along with where in the input it was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// The line the tokenizer was on when the error was reported, starting at 1.
+    pub line: u64,
+    /// The column of the error. html5ever only keeps track of lines, so this is always
+    /// None for now.
+    pub col: Option<u64>,
+    pub message: String,
+}
+
+/// Collect records parse errors together with the line they were reported on.
+struct Collect {
+    line: u64,
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+}
+
+impl Hooks<rcdom::Handle> for Collect {
+    fn parse_error(&mut self, msg: &str) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            line: self.line,
+            col: None,
+            message: msg.to_owned(),
+        });
+    }
+
+    fn set_current_line(&mut self, line: u64) {
+        self.line = line;
+    }
+}
+
+/// DiagnosticFuture parses a stream like `ParserFuture` and resolves to the dom together
+/// with every parse error and the line it happened on, which is what a linter needs.
+/// Errors are reported with html5ever's exact error messages turned on, which are more
+/// detailed but make parsing a little slower. Lines are counted by the tokenizer, so an
+/// error the tree builder reports about a tag is placed on the line the tag ends on.
+#[must_use = "futures do nothing unless polled"]
+pub struct DiagnosticFuture<S, C, E>
+    where S: Stream<Item=C, Error=E>,
+{
+    parser: ParserFuture<S, C, E, HookedSink<RcDom, Collect>>,
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+}
+
+impl<S, C, E> Future for DiagnosticFuture<S, C, E>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    type Item = (RcDom, Vec<Diagnostic>);
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.parser.poll()? {
+            Async::Ready(dom) => {
+                let diagnostics = mem::take(&mut *self.diagnostics.borrow_mut());
+                Ok(Async::Ready((dom, diagnostics)))
+            },
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<S, C, E> ParserFuture<S, C, E, RcDom>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    /// with_diagnostics creates a `DiagnosticFuture`, which resolves to the dom along with
+    /// a `Diagnostic` for every parse error in the input.
+    pub fn with_diagnostics(s: S, dom: RcDom) -> DiagnosticFuture<S, C, E> {
+        let diagnostics = Rc::new(RefCell::new(Vec::new()));
+        let hooks = Collect {
+            line: 1,
+            diagnostics: diagnostics.clone(),
+        };
+        let opts = ParseOpts {
+            tokenizer: TokenizerOpts {
+                exact_errors: true,
+                ..Default::default()
+            },
+            tree_builder: TreeBuilderOpts {
+                exact_errors: true,
+                ..Default::default()
+            },
+        };
+        DiagnosticFuture {
+            parser: ParserFuture::with_opts(s, HookedSink::new(dom, hooks), opts),
+            diagnostics,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::RcDom;
+
+    #[test]
+    fn test_diagnostics() {
+        let chunks = vec!["<!DOCTYPE html>\n<html><body>\n<p>fine</p>\n", "<p>bad &#0; ref\n</b>\n"];
+        let s = stream::iter_ok::<_, ()>(chunks);
+        let (dom, diagnostics) = ParserFuture::with_diagnostics(s, RcDom::default())
+            .wait()
+            .unwrap();
+        assert_eq!(diagnostics.len(), dom.errors.len());
+        let lines: Vec<u64> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, vec![4, 5]);
+        assert!(diagnostics.iter().all(|d| d.col.is_none() && !d.message.is_empty()));
+
+        let clean = stream::iter_ok::<_, ()>(vec!["<!DOCTYPE html><title>ok</title>"]);
+        let (_, diagnostics) = ParserFuture::with_diagnostics(clean, RcDom::default())
+            .wait()
+            .unwrap();
+        assert!(diagnostics.is_empty());
+    }
+}
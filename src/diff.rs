@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use html5ever::QualName;
+
+use common::is_element;
+use hash::element_hashes;
+use rcdom::{self, Node, NodeData};
+
+/// DiffEntry is a single difference found by `dom_diff`, between an element of the old
+/// document and one of the new document.
+#[derive(Clone)]
+pub enum DiffEntry {
+    /// An element only in the new document, its whole subtree is new.
+    Added(rcdom::Handle),
+    /// An element only in the old document, its whole subtree is gone.
+    Removed(rcdom::Handle),
+    /// An element that is in both documents but whose attributes or own text changed.
+    Changed { old: rcdom::Handle, new: rcdom::Handle },
+}
+
+enum Work {
+    Compare(rcdom::Handle, rcdom::Handle),
+    Added(rcdom::Handle),
+    Removed(rcdom::Handle),
+}
+
+fn name(handle: &rcdom::Handle) -> Option<&QualName> {
+    match handle.data {
+        NodeData::Element { ref name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+fn element_children(handle: &rcdom::Handle) -> Vec<rcdom::Handle> {
+    handle.children.borrow().iter().filter(|h| is_element(h)).cloned().collect()
+}
+
+/// own_text concatenates the text nodes directly below an element.
+fn own_text(handle: &rcdom::Handle) -> String {
+    let mut text = String::new();
+    for child in handle.children.borrow().iter() {
+        if let NodeData::Text { ref contents } = child.data {
+            text.push_str(&contents.borrow());
+        }
+    }
+    text
+}
+
+fn same_attrs(old: &rcdom::Handle, new: &rcdom::Handle) -> bool {
+    let sorted = |handle: &rcdom::Handle| -> Vec<(QualName, String)> {
+        let mut attrs: Vec<_> = match handle.data {
+            NodeData::Element { ref attrs, .. } => {
+                attrs.borrow().iter().map(|a| (a.name.clone(), a.value.to_string())).collect()
+            },
+            _ => Vec::new(),
+        };
+        attrs.sort();
+        attrs
+    };
+    sorted(old) == sorted(new)
+}
+
+/// dom_diff compares two documents element by element, for noticing what changed on a
+/// page between two crawls. Entries come out in document order and the diff works like
+/// this:
+///
+/// * Elements are matched up by their position among their parent's element children,
+///   after skipping the longest run of identical children at the start and at the end.
+///   A single element inserted or removed anywhere is reported as just that, while
+///   reordered elements show up as changed, added or removed.
+/// * Matched elements with a different name are reported as one `Removed` and one
+///   `Added`. Those with the same name but different attributes, or different text
+///   directly inside them, are `Changed`. Text changes further down are reported on the
+///   element holding the text.
+/// * Subtrees are compared by their `subtree_hash` first, identical ones are skipped without
+///   being walked, so comments, as well as the order of attributes, never count. The hashes
+///   are computed once for each document up front, so the diff takes linear time however
+///   deep the documents nest.
+pub fn dom_diff(old: &rcdom::RcDom, new: &rcdom::RcDom) -> Vec<DiffEntry> {
+    let old_hashes = element_hashes(&old.document);
+    let new_hashes = element_hashes(&new.document);
+    let hash = |hashes: &HashMap<*const Node, u64>, handle: &rcdom::Handle| hashes[&(&**handle as *const Node)];
+    let mut entries = Vec::new();
+    let mut stack = vec![Work::Compare(Rc::clone(&old.document), Rc::clone(&new.document))];
+    while let Some(work) = stack.pop() {
+        let (old, new) = match work {
+            Work::Added(new) => {
+                entries.push(DiffEntry::Added(new));
+                continue;
+            },
+            Work::Removed(old) => {
+                entries.push(DiffEntry::Removed(old));
+                continue;
+            },
+            Work::Compare(old, new) => (old, new),
+        };
+        if name(&old) != name(&new) {
+            entries.push(DiffEntry::Removed(old));
+            entries.push(DiffEntry::Added(new));
+            continue;
+        }
+        if !same_attrs(&old, &new) || own_text(&old) != own_text(&new) {
+            entries.push(DiffEntry::Changed { old: Rc::clone(&old), new: Rc::clone(&new) });
+        }
+
+        let old_children = element_children(&old);
+        let new_children = element_children(&new);
+        let old_child_hashes: Vec<u64> = old_children.iter().map(|h| hash(&old_hashes, h)).collect();
+        let new_child_hashes: Vec<u64> = new_children.iter().map(|h| hash(&new_hashes, h)).collect();
+        let prefix = old_child_hashes.iter().zip(&new_child_hashes).take_while(|&(a, b)| a == b).count();
+        let suffix = old_child_hashes[prefix..].iter().rev().zip(new_child_hashes[prefix..].iter().rev())
+            .take_while(|&(a, b)| a == b)
+            .count();
+        let old_end = old_children.len() - suffix;
+        let new_end = new_children.len() - suffix;
+
+        let mut work = Vec::new();
+        for i in prefix..old_end.max(new_end) {
+            let old = if i < old_end { old_children.get(i) } else { None };
+            let new = if i < new_end { new_children.get(i) } else { None };
+            match (old, new) {
+                (Some(old), Some(new)) => if old_child_hashes[i] != new_child_hashes[i] {
+                    work.push(Work::Compare(Rc::clone(old), Rc::clone(new)));
+                },
+                (Some(old), None) => work.push(Work::Removed(Rc::clone(old))),
+                (None, Some(new)) => work.push(Work::Added(Rc::clone(new))),
+                (None, None) => {},
+            }
+        }
+        stack.extend(work.into_iter().rev());
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+    use serialize::outer_html;
+
+    fn describe(entry: &DiffEntry) -> String {
+        match *entry {
+            DiffEntry::Added(ref new) => format!("+{}", outer_html(new)),
+            DiffEntry::Removed(ref old) => format!("-{}", outer_html(old)),
+            DiffEntry::Changed { ref old, ref new } => format!("{} => {}", outer_html(old), outer_html(new)),
+        }
+    }
+
+    #[test]
+    fn test_dom_diff() {
+        let old = parse_document(RcDom::default(), Default::default())
+            .one("<ul><li>a</li><li>b</li></ul><p class=x>t<!-- old --></p><span>gone</span>");
+        let new = parse_document(RcDom::default(), Default::default())
+            .one("<ul><li>new</li><li>a</li><li>b</li></ul><p class=y>t</p><em>here</em><div></div>");
+        let entries: Vec<String> = dom_diff(&old, &new).iter().map(describe).collect();
+        assert_eq!(entries, vec![
+            "+<li>new</li>",
+            "<p class=\"x\">t<!-- old --></p> => <p class=\"y\">t</p>",
+            "-<span>gone</span>",
+            "+<em>here</em>",
+            "+<div></div>",
+        ]);
+        assert!(dom_diff(&old, &old).is_empty());
+    }
+}
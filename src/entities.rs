@@ -0,0 +1,373 @@
+use std::char;
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use futures::{Future, Stream, Poll, Async};
+use html5ever::{parse_document, Parser};
+use html5ever::tendril::TendrilSink;
+use html5ever::tendril::stream::Utf8LossyDecoder;
+
+use fut::{Budget, ParserFuture};
+use rcdom::{self, Node, NodeData, RcDom};
+
+/// Longest run of characters after a `&` that is still treated as a possible reference,
+/// the longest named reference html knows about is 33 bytes long.
+const MAX_REFERENCE: usize = 40;
+
+/// How many text nodes of the marked parse are looked at for each text node of the dom.
+const LOOKAHEAD: usize = 8;
+
+// Markers wrapping every reference in the marked copy of the input, all taken from the
+// private use area so the tree builder passes them through as plain text. Each reference
+// ends up as OPEN, the raw spelling shifted into the private use area, DECODED, the
+// reference itself and CLOSE, which leaves whatever html5ever decoded it to between DECODED
+// and CLOSE. Characters of the input that could be mistaken for markers, U+E000 to U+E1FF,
+// are written as ESCAPE followed by the character shifted up by ESCAPE_SHIFT.
+const OPEN: char = '\u{e000}';
+const DECODED: char = '\u{e001}';
+const CLOSE: char = '\u{e002}';
+const ESCAPE: char = '\u{e003}';
+const RAW_BASE: u32 = 0xe100;
+const ESCAPE_SHIFT: u32 = 0x200;
+
+/// EntityRef records a single character reference found in a text node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityRef {
+    /// Byte offset into the text node where the decoded text starts.
+    pub offset: usize,
+    /// The reference as it was spelled in the source, like `&amp;`.
+    pub raw: String,
+    /// What html5ever decoded the reference to.
+    pub decoded: String,
+}
+
+/// RawEntities maps text nodes to the character references they were parsed from.
+#[derive(Default)]
+pub struct RawEntities {
+    // The text nodes that contained references, in document order.
+    nodes: Vec<rcdom::Handle>,
+    refs: HashMap<*const Node, Vec<EntityRef>>,
+}
+
+impl RawEntities {
+    /// get returns the references in the given text node, in the order they appear.
+    pub fn get(&self, node: &rcdom::Handle) -> Option<&[EntityRef]> {
+        self.refs.get(&(&**node as *const Node)).map(|refs| &refs[..])
+    }
+
+    /// contains_entities checks if the given text node contained any references.
+    pub fn contains_entities(&self, node: &rcdom::Handle) -> bool {
+        self.refs.contains_key(&(&**node as *const Node))
+    }
+
+    /// iter yields every text node that contained references, in document order.
+    pub fn iter(&self) -> impl Iterator<Item=(&rcdom::Handle, &[EntityRef])> {
+        self.nodes.iter().map(move |node| (node, &self.refs[&(&**node as *const Node)][..]))
+    }
+
+    fn insert(&mut self, node: rcdom::Handle, refs: Vec<EntityRef>) {
+        self.refs.insert(&*node as *const Node, refs);
+        self.nodes.push(node);
+    }
+}
+
+/// Marker writes the marked copy of the input, where every `&name;` or `&#num;` reference
+/// is wrapped in markers so its spelling survives decoding. References without a trailing
+/// semicolon are left unmarked since marking them would change how html5ever decodes them.
+#[derive(Default)]
+struct Marker {
+    pending: Vec<u8>,
+    // The last byte was 0xEE, which starts the utf-8 encoding of the characters to escape.
+    lead: bool,
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+impl Marker {
+    fn mark(&mut self, out: &mut Vec<u8>) {
+        push_char(out, OPEN);
+        for &b in &self.pending {
+            push_char(out, char::from_u32(RAW_BASE + u32::from(b)).unwrap());
+        }
+        push_char(out, DECODED);
+        out.extend_from_slice(&self.pending);
+        push_char(out, CLOSE);
+        self.pending.clear();
+    }
+
+    fn process(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len());
+        for &b in chunk {
+            if self.lead {
+                self.lead = false;
+                // U+E000 to U+E1FF are 0xEE followed by 0x80 to 0x87, shifting them by
+                // ESCAPE_SHIFT adds 8 to that second byte.
+                if (0x80..=0x87).contains(&b) {
+                    push_char(&mut out, ESCAPE);
+                    out.push(0xee);
+                    out.push(b + 8);
+                    continue;
+                }
+                out.push(0xee);
+            }
+            if !self.pending.is_empty() {
+                if (b.is_ascii_alphanumeric() || b == b'#') && self.pending.len() < MAX_REFERENCE {
+                    self.pending.push(b);
+                    continue;
+                }
+                if b == b';' && self.pending.len() > 1 {
+                    self.pending.push(b);
+                    self.mark(&mut out);
+                    continue;
+                }
+                out.append(&mut self.pending);
+            }
+            match b {
+                b'&' => self.pending.push(b),
+                0xee => self.lead = true,
+                _ => out.push(b),
+            }
+        }
+        out
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let mut out = mem::take(&mut self.pending);
+        if mem::replace(&mut self.lead, false) {
+            out.push(0xee);
+        }
+        out
+    }
+}
+
+/// strip removes the markers from parsed text, leaving the decoded references in place,
+/// and returns the references that html5ever actually decoded.
+fn strip(text: &str) -> (String, Vec<EntityRef>) {
+    let mut out = String::with_capacity(text.len());
+    let mut refs = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find([OPEN, ESCAPE]) {
+        out.push_str(&rest[..start]);
+        if rest[start..].starts_with(ESCAPE) {
+            rest = &rest[start + ESCAPE.len_utf8()..];
+            match rest.chars().next() {
+                Some(c) => {
+                    out.extend(char::from_u32((c as u32).wrapping_sub(ESCAPE_SHIFT)));
+                    rest = &rest[c.len_utf8()..];
+                },
+                None => out.push(ESCAPE),
+            }
+            continue;
+        }
+        rest = &rest[start + OPEN.len_utf8()..];
+        let (decoded_at, close_at) = match (rest.find(DECODED), rest.find(CLOSE)) {
+            (Some(d), Some(c)) if d < c => (d, c),
+            _ => {
+                out.push(OPEN);
+                continue;
+            },
+        };
+        let raw: String = rest[..decoded_at].chars()
+            .filter_map(|c| char::from_u32((c as u32).wrapping_sub(RAW_BASE)))
+            .collect();
+        let decoded = &rest[decoded_at + DECODED.len_utf8()..close_at];
+        // Inside script, style and the like nothing gets decoded.
+        if raw != decoded {
+            refs.push(EntityRef {
+                offset: out.len(),
+                raw,
+                decoded: decoded.to_string(),
+            });
+        }
+        out.push_str(decoded);
+        rest = &rest[close_at + CLOSE.len_utf8()..];
+    }
+    out.push_str(rest);
+    (out, refs)
+}
+
+/// text_nodes lists the text nodes of a dom and their text in document order, including
+/// those inside templates.
+fn text_nodes(dom: &RcDom) -> Vec<(rcdom::Handle, String)> {
+    let mut nodes = Vec::new();
+    let mut stack = vec![Rc::clone(&dom.document)];
+    while let Some(node) = stack.pop() {
+        match node.data {
+            NodeData::Text { ref contents } => {
+                let text = contents.borrow().to_string();
+                nodes.push((Rc::clone(&node), text));
+            },
+            NodeData::Element { template_contents: Some(ref contents), .. } => {
+                stack.push(Rc::clone(contents));
+            },
+            _ => {},
+        }
+        stack.extend(node.children.borrow().iter().rev().cloned());
+    }
+    nodes
+}
+
+/// match_up pairs the text nodes of the dom with the text nodes of the dom parsed from the
+/// marked input, in document order, and records the references of each pair. The markers
+/// aren't whitespace, so the marked input isn't always built into the same tree: a `&#32;`
+/// directly inside a `<table>` gets moved in front of it for example. Text nodes are only
+/// paired up if they hold the same text once the markers are stripped, the text nodes
+/// around such a difference are still found by looking a few nodes ahead.
+fn match_up(dom: &RcDom, marked: &RcDom) -> RawEntities {
+    let mut marked: Vec<(String, Vec<EntityRef>)> = text_nodes(marked).iter()
+        .map(|(_, text)| strip(text))
+        .collect();
+    let mut entities = RawEntities::default();
+    let mut next = 0;
+    for (node, text) in text_nodes(dom) {
+        let found = marked[next..].iter().take(LOOKAHEAD)
+            .position(|(stripped, _)| *stripped == text);
+        if let Some(i) = found {
+            let refs = mem::take(&mut marked[next + i].1);
+            next += i + 1;
+            if !refs.is_empty() {
+                entities.insert(node, refs);
+            }
+        }
+    }
+    entities
+}
+
+type DocumentParser = Utf8LossyDecoder<Parser<RcDom>>;
+
+/// RawEntitiesFuture parses a stream like `ParserFuture` and resolves to the dom together
+/// with the character references each text node was parsed from.
+#[must_use = "futures do nothing unless polled"]
+pub struct RawEntitiesFuture<S> {
+    stream: S,
+    marker: Marker,
+    // The parser building the dom and the one parsing the marked input.
+    parsers: Option<(DocumentParser, DocumentParser)>,
+}
+
+impl<S, C, E> Future for RawEntitiesFuture<S>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    type Item = (RcDom, RawEntities);
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    let (ref mut parser, ref mut marked) = *self.parsers.as_mut()
+                        .expect("polled RawEntitiesFuture after completion");
+                    parser.process(chunk.as_ref().into());
+                    marked.process(self.marker.process(chunk.as_ref())[..].into());
+                    if budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Async::Ready(None) => {
+                    let (parser, mut marked) = self.parsers.take()
+                        .expect("polled RawEntitiesFuture after completion");
+                    marked.process(self.marker.finish()[..].into());
+                    let dom = parser.finish();
+                    let entities = match_up(&dom, &marked.finish());
+                    return Ok(Async::Ready((dom, entities)));
+                },
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S, C, E> ParserFuture<S, C, E, RcDom>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    /// with_raw_entities creates a future that keeps track of the character references
+    /// html5ever decodes while parsing. It resolves to the dom, exactly as `ParserFuture`
+    /// would build it, and a `RawEntities` recording for each text node which references it
+    /// was parsed from and how they were spelled. Only references terminated by a semicolon
+    /// are tracked.
+    ///
+    /// The input is parsed twice, once as is and once with markers around every reference,
+    /// and the text nodes of both are matched up afterwards. Where the markers make the tree
+    /// builder place text differently, like whitespace references directly inside a
+    /// `<table>`, the text nodes involved may not be flagged.
+    pub fn with_raw_entities(s: S, dom: RcDom) -> RawEntitiesFuture<S> {
+        RawEntitiesFuture {
+            stream: s,
+            marker: Marker::default(),
+            parsers: Some((
+                parse_document(dom, Default::default()).from_utf8(),
+                parse_document(RcDom::default(), Default::default()).from_utf8(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::{NodeData, RcDom};
+    use common::NodeTraverser;
+    use serialize::canonicalize;
+    use extract::attr;
+    use select::select;
+
+    fn parse(chunks: Vec<&'static str>) -> (RcDom, RawEntities) {
+        let s = stream::iter_ok::<_, ()>(chunks);
+        ParserFuture::with_raw_entities(s, RcDom::default()).wait().unwrap()
+    }
+
+    #[test]
+    fn test_raw_entities() {
+        let (dom, entities) = parse(vec![
+            "<p title='a&amp;b'>fish &am", "p; chips &#x3C;3 &unknown; & more</p>",
+            "<!-- x &lt; y --><script>a &amp;&amp; b</script><p>plain</p>",
+        ]);
+        let p = &select(&dom, "p").unwrap()[0];
+        assert_eq!(attr(p, "title").unwrap(), "a&b");
+        let text = p.children.borrow()[0].clone();
+        match text.data {
+            NodeData::Text { ref contents } => {
+                assert_eq!(&**contents.borrow(), "fish & chips <3 &unknown; & more");
+            },
+            _ => panic!("expected text"),
+        }
+        let refs = entities.get(&text).unwrap();
+        assert_eq!(refs.len(), 2);
+        assert_eq!(refs[0], EntityRef { offset: 5, raw: "&amp;".into(), decoded: "&".into() });
+        assert_eq!(refs[1].raw, "&#x3C;");
+        assert_eq!(refs[1].offset, 13);
+        assert_eq!(entities.iter().count(), 1);
+
+        let flagged = NodeTraverser::new(&dom)
+            .filter(|node| entities.contains_entities(node))
+            .count();
+        assert_eq!(flagged, 1);
+        assert!(canonicalize(&dom).contains("<!-- x &lt; y -->"));
+    }
+
+    #[test]
+    fn test_raw_entities_keep_dom() {
+        use html5ever::parse_document;
+        use html5ever::tendril::TendrilSink;
+
+        let html = "<table>&#32;<tr><td>a&amp;b</td></tr></table>\
+                    <p>\u{e000}\u{e101} &lt;\u{e0}\u{ee}\u{e2}</p><pre>&#10;x</pre>";
+        let plain = parse_document(RcDom::default(), Default::default()).one(html);
+        let (dom, entities) = parse(vec![html]);
+        assert_eq!(canonicalize(&dom), canonicalize(&plain));
+
+        let refs: Vec<(usize, &str)> = entities.iter()
+            .flat_map(|(_, refs)| refs.iter().map(|r| (r.offset, &r.raw[..])))
+            .collect();
+        assert_eq!(refs, vec![(0, "&#32;"), (1, "&amp;"), (7, "&lt;")]);
+    }
+}
@@ -0,0 +1,124 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use html5ever::{LocalName, QualName};
+
+/// SelectorError is returned when a CSS selector can't be parsed, or uses syntax that the
+/// selector engine in this crate doesn't support.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    selector: String,
+    reason: &'static str,
+}
+
+impl SelectorError {
+    pub(crate) fn new(selector: &str, reason: &'static str) -> Self {
+        SelectorError {
+            selector: selector.to_owned(),
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid selector `{}`: {}", self.selector, self.reason)
+    }
+}
+
+impl error::Error for SelectorError {}
+
+/// Abort describes why a parse was stopped before the input was exhausted, or why its
+/// result was rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Abort {
+    /// An element was nested deeper than the configured maximum depth.
+    MaxDepthExceeded(usize),
+    /// The check run on the first bytes of the input decided it isn't worth parsing.
+    SniffRejected,
+    /// An element with the given tag name had more than the configured maximum number of
+    /// attributes.
+    TooManyAttributes(LocalName, usize),
+    /// The input ended while these elements were still open, so it was probably cut off.
+    Truncated(Vec<QualName>),
+    /// The sink panicked with this message while building the document.
+    SinkPanicked(String),
+}
+
+impl fmt::Display for Abort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Abort::MaxDepthExceeded(max) => write!(f, "document nested deeper than {} levels", max),
+            Abort::SniffRejected => write!(f, "input rejected after sniffing its first bytes"),
+            Abort::TooManyAttributes(ref tag, max) => {
+                write!(f, "<{}> element has more than {} attributes", tag, max)
+            },
+            Abort::Truncated(ref unclosed) => {
+                write!(f, "input ended with {} unclosed elements", unclosed.len())?;
+                if let Some(first) = unclosed.first() {
+                    write!(f, ", starting with <{}>", first.local)?;
+                }
+                Ok(())
+            },
+            Abort::SinkPanicked(ref msg) => write!(f, "sink panicked: {}", msg),
+        }
+    }
+}
+
+/// Error is returned by the parser futures that can stop a parse early. It either wraps an
+/// error from the upstream stream, or says why the parse was aborted.
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The stream being parsed returned an error.
+    Stream(E),
+    /// The parse was stopped because one of the configured limits was exceeded, or the
+    /// input was rejected.
+    Aborted(Abort),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Stream(ref err) => write!(f, "stream error: {}", err),
+            Error::Aborted(ref abort) => write!(f, "parse aborted: {}", abort),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Stream(ref err) => Some(err),
+            Error::Aborted(_) => None,
+        }
+    }
+}
+
+/// WriteError is returned by `parse_and_write`, it wraps either an error from the stream
+/// being parsed or one from writing the serialized document.
+#[derive(Debug)]
+pub enum WriteError<E> {
+    /// The stream being parsed returned an error.
+    Stream(E),
+    /// Writing the serialized document failed.
+    Io(io::Error),
+}
+
+impl<E: fmt::Display> fmt::Display for WriteError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteError::Stream(ref err) => write!(f, "stream error: {}", err),
+            WriteError::Io(ref err) => write!(f, "write error: {}", err),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for WriteError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            WriteError::Stream(ref err) => Some(err),
+            WriteError::Io(ref err) => Some(err),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+
+use html5ever::LocalName;
+use html5ever::tendril::StrTendril;
+
+use common::{elements, head_and_body, is_html, root_element};
+use errors::SelectorError;
+use rcdom::{self, NodeData};
+use select::Selector;
+use text::text_content;
+
+/// Extract says what to pull off of each element matched by a query passed to `extract`.
+pub enum Extract<'a> {
+    /// The text content of the element, with surrounding whitespace trimmed.
+    Text,
+    /// The value of the named attribute. Elements without the attribute are skipped.
+    Attr(&'a str),
+}
+
+/// attr returns the value of the named attribute on an element, or None for missing
+/// attributes and non-element nodes.
+pub(crate) fn attr(handle: &rcdom::Handle, name: &str) -> Option<String> {
+    match handle.data {
+        NodeData::Element { ref attrs, .. } => {
+            attrs.borrow().iter().find(|a| &*a.name.local == name).map(|a| a.value.to_string())
+        },
+        _ => None,
+    }
+}
+
+/// attrs yields the name and value of each attribute of an element, in source order, or
+/// nothing for other kinds of nodes. The attributes are copied out up front, so the
+/// element can be changed while the iterator is still in use. Names are local names, the
+/// namespace of attributes like `xlink:href` is dropped.
+pub fn attrs(handle: &rcdom::Handle) -> impl Iterator<Item=(LocalName, StrTendril)> {
+    let attrs: Vec<(LocalName, StrTendril)> = match handle.data {
+        NodeData::Element { ref attrs, .. } => {
+            attrs.borrow().iter().map(|a| (a.name.local.clone(), a.value.clone())).collect()
+        },
+        _ => Vec::new(),
+    };
+    attrs.into_iter()
+}
+
+/// in_class_list checks if a whitespace separated class attribute contains `class`.
+pub(crate) fn in_class_list(list: &str, class: &str) -> bool {
+    list.split_whitespace().any(|c| c == class)
+}
+
+/// extract runs a set of named queries against a document and collects the results into a
+/// map keyed by name. Each query is a selector plus what to extract from the elements it
+/// matches, results are in document order and queries without matches map to an empty
+/// Vec. This packages up the common "pull these fields off a page" scraping workflow.
+/// # Examples
+/// ```rust
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use html5ever::parse_document;
+/// use html5ever::rcdom::RcDom;
+/// use html5ever::tendril::TendrilSink;
+/// use html5ever_stream::{extract, Extract};
+///
+/// const TEST_HTML: &'static str = "<title> test </title><a href='/a'>a</a><a href='/b'>b</a>";
+/// let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+/// let fields = extract(&dom, &[
+///     ("title", "head > title", Extract::Text),
+///     ("links", "a", Extract::Attr("href")),
+/// ]).unwrap();
+/// assert_eq!(fields["title"], vec!["test"]);
+/// assert_eq!(fields["links"], vec!["/a", "/b"]);
+/// ```
+pub fn extract(dom: &rcdom::RcDom, queries: &[(&str, &str, Extract)])
+    -> Result<HashMap<String, Vec<String>>, SelectorError>
+{
+    let selectors = queries.iter()
+        .map(|&(_, selector, _)| Selector::parse(selector))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut results: HashMap<String, Vec<String>> = queries.iter()
+        .map(|&(name, _, _)| (name.to_owned(), Vec::new()))
+        .collect();
+    for handle in elements(&dom.document) {
+        for (&(name, _, ref what), selector) in queries.iter().zip(&selectors) {
+            if !selector.matches(&handle) {
+                continue;
+            }
+            let value = match *what {
+                Extract::Text => Some(text_content(&handle).trim().to_owned()),
+                Extract::Attr(attr_name) => attr(&handle, attr_name),
+            };
+            if let Some(value) = value {
+                results.get_mut(name).expect("every query has an entry").push(value);
+            }
+        }
+    }
+    Ok(results)
+}
+
+/// ImageInfo describes an `<img>` element found by `images`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageInfo {
+    pub src: String,
+    pub srcset: Option<String>,
+    pub alt: Option<String>,
+    /// The raw value of the width attribute, it isn't validated or parsed.
+    pub width: Option<String>,
+    /// The raw value of the height attribute, it isn't validated or parsed.
+    pub height: Option<String>,
+}
+
+/// images collects every `<img>` element in the document that has a src attribute, in
+/// document order. `<picture>` and `<source>` elements are not looked at.
+pub fn images(dom: &rcdom::RcDom) -> Vec<ImageInfo> {
+    elements(&dom.document).filter(|h| is_html(h, &local_name!("img"))).filter_map(|h| {
+        Some(ImageInfo {
+            src: attr(&h, "src")?,
+            srcset: attr(&h, "srcset"),
+            alt: attr(&h, "alt"),
+            width: attr(&h, "width"),
+            height: attr(&h, "height"),
+        })
+    }).collect()
+}
+
+/// ScriptInfo describes a `<script>` element found by `scripts`. `script_type` is the raw
+/// type attribute, like `module` or `application/ld+json`, None for a classic script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptInfo {
+    /// A script loaded from the url in its src attribute, as written.
+    External { src: String, script_type: Option<String> },
+    /// A script written out in the page.
+    Inline { text: String, script_type: Option<String> },
+}
+
+/// scripts collects every html `<script>` element in the document, in document order, for
+/// auditing what a page runs against a content security policy. Like in browsers a script
+/// with a src attribute is external and whatever text it has inside is ignored. Scripts
+/// inside `<svg>` are not included.
+pub fn scripts(dom: &rcdom::RcDom) -> Vec<ScriptInfo> {
+    elements(&dom.document).filter(|h| is_html(h, &local_name!("script"))).map(|h| {
+        let script_type = attr(&h, "type");
+        match attr(&h, "src") {
+            Some(src) => ScriptInfo::External { src, script_type },
+            None => ScriptInfo::Inline { text: text_content(&h), script_type },
+        }
+    }).collect()
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+/// document_lang returns the language a document declares for itself. This is the lang
+/// attribute of the `<html>` element, falling back to the content of a
+/// `<meta http-equiv="Content-Language">` element. Empty declarations are ignored.
+pub fn document_lang(dom: &rcdom::RcDom) -> Option<String> {
+    if let Some(lang) = root_element(dom).and_then(|h| attr(&h, "lang")).and_then(non_empty) {
+        return Some(lang);
+    }
+    elements(&dom.document)
+        .filter(|h| is_html(h, &local_name!("meta")))
+        .find(|h| {
+            attr(h, "http-equiv").is_some_and(|v| v.eq_ignore_ascii_case("content-language"))
+        })
+        .and_then(|h| attr(&h, "content"))
+        .and_then(non_empty)
+}
+
+/// base_href returns the href of the document's `<base>` element, which replaces the
+/// document's own URL when resolving relative links. Like browsers only the first `<base>`
+/// with an href counts, html5ever moves it into the head when it shows up elsewhere.
+pub fn base_href(dom: &rcdom::RcDom) -> Option<String> {
+    elements(&dom.document)
+        .filter(|h| is_html(h, &local_name!("base")))
+        .filter_map(|h| attr(&h, "href"))
+        .next()
+        .and_then(non_empty)
+}
+
+/// canonical_url returns the href of the first `<link rel="canonical">` in the document's
+/// head, the URL the page says search engines should index it under. rel is a list of
+/// keywords, matched ignoring ascii case. Canonical links outside the head don't count,
+/// and the href is returned as written, resolve it against `base_href` if needed.
+pub fn canonical_url(dom: &rcdom::RcDom) -> Option<String> {
+    let head = head_and_body(dom).0?;
+    elements(&head)
+        .filter(|h| is_html(h, &local_name!("link")))
+        .filter(|h| attr(h, "rel").is_some_and(|rel| {
+            rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("canonical"))
+        }))
+        .filter_map(|h| attr(&h, "href"))
+        .next()
+        .and_then(non_empty)
+}
+
+/// get_element_by_id returns the first element in document order whose id attribute is
+/// exactly `id`, stopping the traversal as soon as it is found. Ids are supposed to be
+/// unique, so like `document.getElementById` any later duplicates are never looked at.
+pub fn get_element_by_id(dom: &rcdom::RcDom, id: &str) -> Option<rcdom::Handle> {
+    elements(&dom.document).find(|h| attr(h, "id").as_ref().map(|v| &v[..]) == Some(id))
+}
+
+/// heading_outline lists the `<h1>` to `<h6>` elements of the document in document order,
+/// each with its level and its text with whitespace collapsed. Going through the levels
+/// shows the structure a screen reader presents, including any skipped levels.
+pub fn heading_outline(dom: &rcdom::RcDom) -> Vec<(u8, String)> {
+    elements(&dom.document)
+        .filter_map(|h| {
+            let level = match h.data {
+                NodeData::Element { ref name, .. } if name.ns == ns!(html) => match name.local {
+                    local_name!("h1") => 1,
+                    local_name!("h2") => 2,
+                    local_name!("h3") => 3,
+                    local_name!("h4") => 4,
+                    local_name!("h5") => 5,
+                    local_name!("h6") => 6,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            let text = text_content(&h).split_whitespace().collect::<Vec<_>>().join(" ");
+            Some((level, text))
+        })
+        .collect()
+}
+
+/// duplicate_ids returns the ids used by more than one element, which the html spec
+/// doesn't allow. Each one is listed once, in the order it first appears in the document.
+/// Empty ids are not counted, they are invalid whether they repeat or not.
+pub fn duplicate_ids(dom: &rcdom::RcDom) -> Vec<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    for id in elements(&dom.document).filter_map(|h| attr(&h, "id")) {
+        if id.is_empty() {
+            continue;
+        }
+        let count = counts.entry(id.clone()).or_insert(0);
+        if *count == 0 {
+            order.push(id);
+        }
+        *count += 1;
+    }
+    order.retain(|id| counts[id] > 1);
+    order
+}
+
+/// get_elements_by_class returns every element whose class attribute lists `class`, in
+/// document order, like `document.getElementsByClassName` with a single class name.
+pub fn get_elements_by_class(dom: &rcdom::RcDom, class: &str) -> Vec<rcdom::Handle> {
+    elements(&dom.document)
+        .filter(|h| attr(h, "class").into_iter().any(|list| in_class_list(&list, class)))
+        .collect()
+}
+
+/// parse_table reads the cells of a `<table>` element into rows of trimmed cell text.
+/// Rows are taken from the table and its `<thead>`, `<tbody>` and `<tfoot>` sections in
+/// document order, and both `<td>` and `<th>` count as cells. A cell with a colspan is
+/// repeated that many times, up to 1000 like browsers allow, while rowspan is ignored, so
+/// rows below a spanning cell come out shorter. Nested tables are not looked into, a cell
+/// holding one just gets its text. Anything but a table yields no rows.
+pub fn parse_table(handle: &rcdom::Handle) -> Vec<Vec<String>> {
+    if !is_html(handle, &local_name!("table")) {
+        return Vec::new();
+    }
+    let mut rows = Vec::new();
+    for child in handle.children.borrow().iter() {
+        let is_section = is_html(child, &local_name!("thead"))
+            || is_html(child, &local_name!("tbody"))
+            || is_html(child, &local_name!("tfoot"));
+        if is_section {
+            let section = child.children.borrow();
+            rows.extend(section.iter().filter(|h| is_html(h, &local_name!("tr"))).map(table_row));
+        } else if is_html(child, &local_name!("tr")) {
+            rows.push(table_row(child));
+        }
+    }
+    rows
+}
+
+fn table_row(tr: &rcdom::Handle) -> Vec<String> {
+    let mut row = Vec::new();
+    for cell in tr.children.borrow().iter() {
+        if !is_html(cell, &local_name!("td")) && !is_html(cell, &local_name!("th")) {
+            continue;
+        }
+        let span = attr(cell, "colspan")
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .map(|n| n.clamp(1, 1000))
+            .unwrap_or(1);
+        let text = text_content(cell).trim().to_owned();
+        for _ in 0..span {
+            row.push(text.clone());
+        }
+    }
+    row
+}
+
+/// jsonld returns the raw text of every `<script type="application/ld+json">` element in
+/// the document, in document order, which is where pages embed their structured data. The
+/// text is returned as is, it isn't checked to be valid JSON. With the `serde_json`
+/// feature `jsonld_values` parses it as well.
+pub fn jsonld(dom: &rcdom::RcDom) -> Vec<String> {
+    elements(&dom.document)
+        .filter(|h| is_html(h, &local_name!("script")))
+        .filter(|h| match attr(h, "type") {
+            Some(kind) => kind.trim().eq_ignore_ascii_case("application/ld+json"),
+            None => false,
+        })
+        .map(|h| text_content(&h))
+        .collect()
+}
+
+/// jsonld_values parses the blocks `jsonld` finds as JSON. Malformed blocks come back as
+/// their parse error, so one broken block doesn't hide the others. Only available with the
+/// `serde_json` feature.
+#[cfg(feature = "serde_json")]
+pub fn jsonld_values(dom: &rcdom::RcDom) -> Vec<Result<serde_json::Value, serde_json::Error>> {
+    jsonld(dom).iter().map(|json| serde_json::from_str(json)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    #[test]
+    fn test_extract() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<p class='price'> 10 </p><a>no href</a><p class='price'>20</p>");
+        let fields = extract(&dom, &[
+            ("prices", "p.price", Extract::Text),
+            ("links", "a", Extract::Attr("href")),
+            ("missing", "table", Extract::Text),
+        ]).unwrap();
+        assert_eq!(fields["prices"], vec!["10", "20"]);
+        assert!(fields["links"].is_empty());
+        assert!(fields["missing"].is_empty());
+        assert!(extract(&dom, &[("bad", "p:first-child", Extract::Text)]).is_err());
+    }
+
+    #[test]
+    fn test_document_lang() {
+        let lang = |html: &str| {
+            document_lang(&parse_document(RcDom::default(), Default::default()).one(html))
+        };
+        assert_eq!(lang("<html lang='en-US'><meta http-equiv='content-language' content='de'>"),
+                   Some("en-US".into()));
+        assert_eq!(lang("<html lang=''><meta http-equiv='Content-Language' content='de'>"),
+                   Some("de".into()));
+        assert_eq!(lang("<html><body>no language</body></html>"), None);
+    }
+
+    #[test]
+    fn test_base_href() {
+        let base = |html: &str| {
+            base_href(&parse_document(RcDom::default(), Default::default()).one(html))
+        };
+        assert_eq!(base("<head><base target='_blank'><base href=' /docs/ '><base href='/x/'>"),
+                   Some("/docs/".into()));
+        assert_eq!(base("<head><title>no base</title></head>"), None);
+    }
+
+    #[test]
+    fn test_canonical_url() {
+        let canonical = |html: &str| {
+            canonical_url(&parse_document(RcDom::default(), Default::default()).one(html))
+        };
+        assert_eq!(canonical("<link rel='stylesheet' href='/a.css'><link rel='Alternate CANONICAL' \
+                              href=' https://example.com/a '><link rel=canonical href='/b'>"),
+                   Some("https://example.com/a".into()));
+        assert_eq!(canonical("<title>t</title><body><div></div><link rel='canonical' href='/body'>"), None);
+    }
+
+    #[test]
+    fn test_get_element_by_id() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<div id='main'><p id='x'>first</p><p id='x'>second</p></div><a id='Main'></a>");
+        let p = get_element_by_id(&dom, "x").unwrap();
+        assert_eq!(text_content(&p), "first");
+        assert!(get_element_by_id(&dom, "main").is_some());
+        assert!(get_element_by_id(&dom, "missing").is_none());
+        assert!(get_element_by_id(&dom, "").is_none());
+    }
+
+    #[test]
+    fn test_heading_outline() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<h1>Title</h1><section><h3> Skipped\n <em>level</em></h3></section><h2>Two</h2>");
+        assert_eq!(heading_outline(&dom), vec![
+            (1, "Title".to_owned()),
+            (3, "Skipped level".to_owned()),
+            (2, "Two".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_duplicate_ids() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<p id='b'></p><p id='a'></p><p id='a'></p><p id='b'></p><p id='b'></p>\
+                  <p id='c'></p><p id=''></p><p id=''></p>");
+        assert_eq!(duplicate_ids(&dom), vec!["b", "a"]);
+    }
+
+    #[test]
+    fn test_attrs() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<a href='/a' DATA-x=''>a</a>");
+        let a = ::descendants_by_tag(&dom.document, "a").next().unwrap();
+        let found: Vec<String> = attrs(&a).map(|(n, v)| format!("{}={}", n, v)).collect();
+        assert_eq!(found, vec!["href=/a", "data-x="]);
+        assert_eq!(attrs(&dom.document).count(), 0);
+    }
+
+    #[test]
+    fn test_get_elements_by_class() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<p class='a'>1</p><p class=' b\ta  c'>2</p><p class='ab'>3</p><p>4</p>");
+        let found: Vec<String> = get_elements_by_class(&dom, "a").iter().map(text_content).collect();
+        assert_eq!(found, vec!["1", "2"]);
+        assert!(get_elements_by_class(&dom, "").is_empty());
+    }
+
+    #[test]
+    fn test_scripts() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<script src='/app.js' type=module>ignored</script><p><script>run()</script>\
+                  <svg><script>svg()</script></svg>");
+        assert_eq!(scripts(&dom), vec![
+            ScriptInfo::External { src: "/app.js".into(), script_type: Some("module".into()) },
+            ScriptInfo::Inline { text: "run()".into(), script_type: None },
+        ]);
+    }
+
+    #[test]
+    fn test_images() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<img alt='missing src'><img src='a.png' alt='a' width='10' height='20'>\
+                  <img src='b.png' srcset='b2.png 2x'>");
+        let found = images(&dom);
+        assert_eq!(found, vec![
+            ImageInfo {
+                src: "a.png".into(),
+                srcset: None,
+                alt: Some("a".into()),
+                width: Some("10".into()),
+                height: Some("20".into()),
+            },
+            ImageInfo {
+                src: "b.png".into(),
+                srcset: Some("b2.png 2x".into()),
+                alt: None,
+                width: None,
+                height: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_table() {
+        let dom = parse_document(RcDom::default(), Default::default()).one("<table>\
+            <thead><tr><th>name</th><th colspan=2>score</th></tr></thead>\
+            <tr><td> a </td><td>1</td><td>2</td></tr>\
+            <tr><td><table><tr><td>nested</td></tr></table></td></tr>\
+            </table>");
+        let table = elements(&dom.document).find(|h| is_html(h, &local_name!("table"))).unwrap();
+        assert_eq!(parse_table(&table), vec![
+            vec!["name", "score", "score"],
+            vec!["a", "1", "2"],
+            vec!["nested"],
+        ]);
+        assert!(parse_table(&dom.document).is_empty());
+    }
+
+    #[test]
+    fn test_jsonld() {
+        let dom = parse_document(RcDom::default(), Default::default()).one("\
+            <script type='application/ld+json'>{\"@type\": \"Article\"}</script>\
+            <script>var notJson = 1;</script>\
+            <script type=' Application/LD+JSON '>{broken</script>");
+        assert_eq!(jsonld(&dom), vec!["{\"@type\": \"Article\"}", "{broken"]);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_jsonld_values() {
+        let dom = parse_document(RcDom::default(), Default::default()).one("\
+            <script type='application/ld+json'>{\"@type\": \"Article\"}</script>\
+            <script type='application/ld+json'>{broken</script>");
+        let values = jsonld_values(&dom);
+        assert_eq!(values[0].as_ref().unwrap()["@type"], "Article");
+        assert!(values[1].is_err());
+    }
+}
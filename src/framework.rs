@@ -0,0 +1,103 @@
+use common::elements;
+use errors::SelectorError;
+use rcdom;
+use select::Selector;
+
+/// Framework is a site technology that `detect_framework` can recognize from the markup
+/// it leaves behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Framework {
+    Amp,
+    NextJs,
+    Nuxt,
+    Gatsby,
+    React,
+    Angular,
+    Vue,
+    /// A framework recognized by a marker passed to `detect_framework_with`.
+    Other(String),
+}
+
+/// FrameworkMarker pairs a framework with a selector matching an element only that
+/// framework produces.
+pub struct FrameworkMarker {
+    framework: Framework,
+    selector: Selector,
+}
+
+impl FrameworkMarker {
+    pub fn new(framework: Framework, selector: &str) -> Result<Self, SelectorError> {
+        Ok(FrameworkMarker {
+            framework,
+            selector: Selector::parse(selector)?,
+        })
+    }
+}
+
+/// default_markers returns the markers `detect_framework` looks for. Frameworks built on
+/// top of others come before the ones they are built on, Next.js pages also look like
+/// React pages for example. Extend the list and pass it to `detect_framework_with` to
+/// recognize more.
+pub fn default_markers() -> Vec<FrameworkMarker> {
+    let markers = [
+        (Framework::Amp, "html[amp]"),
+        (Framework::NextJs, "#__next"),
+        (Framework::Gatsby, "#___gatsby"),
+        (Framework::Nuxt, "#__nuxt, [data-n-head]"),
+        (Framework::React, "[data-reactroot], [data-reactid]"),
+        (Framework::Angular, "[ng-version], [ng-app]"),
+        (Framework::Vue, "[data-v-app], [data-server-rendered]"),
+    ];
+    markers.iter()
+        .map(|&(ref framework, selector)| {
+            FrameworkMarker::new(framework.clone(), selector).expect("default markers are valid")
+        })
+        .collect()
+}
+
+/// detect_framework guesses which framework built a page by looking for the markers in
+/// `default_markers`, returning the first framework in that list with a match. This is a
+/// heuristic: server rendered pages usually carry these markers, but a page can strip
+/// them, and AMP pages marked with `<html ⚡>` instead of `<html amp>` aren't recognized.
+pub fn detect_framework(dom: &rcdom::RcDom) -> Option<Framework> {
+    detect_framework_with(dom, &default_markers())
+}
+
+/// detect_framework_with is `detect_framework` with a custom list of markers, the first
+/// marker matching any element of the document wins.
+pub fn detect_framework_with(dom: &rcdom::RcDom, markers: &[FrameworkMarker]) -> Option<Framework> {
+    markers.iter()
+        .find(|marker| elements(&dom.document).any(|h| marker.selector.matches(&h)))
+        .map(|marker| marker.framework.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn parse(html: &str) -> rcdom::RcDom {
+        parse_document(RcDom::default(), Default::default()).one(html)
+    }
+
+    #[test]
+    fn test_detect_framework() {
+        assert_eq!(detect_framework(&parse("<html amp><p>a")), Some(Framework::Amp));
+        assert_eq!(detect_framework(&parse("<div id=__next><div data-reactroot>")),
+                   Some(Framework::NextJs));
+        assert_eq!(detect_framework(&parse("<div data-reactroot>")), Some(Framework::React));
+        assert_eq!(detect_framework(&parse("<app-root ng-version=12>")), Some(Framework::Angular));
+        assert_eq!(detect_framework(&parse("<p>plain")), None);
+    }
+
+    #[test]
+    fn test_custom_markers() {
+        let mut markers = default_markers();
+        markers.push(FrameworkMarker::new(Framework::Other("svelte".into()), "[class|=svelte]").unwrap());
+        assert_eq!(detect_framework_with(&parse("<p class=svelte-1x2y>"), &markers),
+                   Some(Framework::Other("svelte".into())));
+    }
+}
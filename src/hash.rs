@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::hash::Hasher;
+use std::rc::Rc;
+
+use common::{EventIter, DomEvent, Step};
+use rcdom::{self, Node, NodeData};
+
+const OPEN: u8 = 0xfd;
+const CLOSE: u8 = 0xfe;
+// 0xff never appears in utf-8, so it safely terminates every string fed to the hasher.
+const END: u8 = 0xff;
+
+/// Fnv is a 64 bit FNV-1a hasher. Unlike `DefaultHasher` its output is specified, so
+/// hashes stay the same across Rust releases and can be persisted.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+fn write_str<H: Hasher>(hasher: &mut H, s: &str) {
+    hasher.write(s.as_bytes());
+    hasher.write_u8(END);
+}
+
+/// write_start hashes an element's name and its attributes, sorted by name.
+fn write_start<H: Hasher>(hasher: &mut H, handle: &rcdom::Handle) {
+    if let NodeData::Element { ref name, ref attrs, .. } = handle.data {
+        hasher.write_u8(OPEN);
+        write_str(hasher, &name.ns);
+        write_str(hasher, &name.local);
+        let attrs = attrs.borrow();
+        let mut sorted: Vec<_> = attrs.iter().collect();
+        sorted.sort_by_key(|a| (&*a.name.ns, &*a.name.local));
+        for attr in sorted {
+            write_str(hasher, &attr.name.ns);
+            write_str(hasher, &attr.name.local);
+            write_str(hasher, &attr.value);
+        }
+    }
+}
+
+/// subtree_hash computes a stable 64 bit hash of the subtree rooted at the given node.
+/// The hash covers, in document order:
+///
+/// * every element's namespace and local name, and where its children begin and end
+/// * every element's attributes (namespace, local name and value), sorted by name so
+///   attribute order doesn't matter
+/// * the exact contents of every text node
+///
+/// Comments, doctypes and processing instructions are ignored. Two subtrees with the same
+/// structure hash the same no matter which document they came from. The hash function is
+/// FNV-1a, so values are stable across runs and platforms.
+pub fn subtree_hash(handle: &rcdom::Handle) -> u64 {
+    let mut hasher = Fnv::default();
+    for event in EventIter::from_handle(handle) {
+        match event {
+            DomEvent::Open(handle) => write_start(&mut hasher, &handle),
+            DomEvent::Close(_) => hasher.write_u8(CLOSE),
+            DomEvent::Text(contents) => write_str(&mut hasher, &contents),
+        }
+    }
+    hasher.finish()
+}
+
+/// element_hashes hashes every element below `root` in a single pass, children before
+/// their parents, for comparing many subtrees of the same document without hashing each
+/// node once for every ancestor. The values aren't the ones `subtree_hash` returns, but
+/// they cover the same things, so two elements get the same hash exactly when
+/// `subtree_hash` would give them the same one, barring collisions.
+pub(crate) fn element_hashes(root: &rcdom::Handle) -> HashMap<*const Node, u64> {
+    let mut hashes = HashMap::new();
+    let mut stack = vec![Step::Enter(Rc::clone(root))];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Enter(handle) => {
+                stack.push(Step::Exit(Rc::clone(&handle)));
+                for child in handle.children.borrow().iter().rev() {
+                    if let NodeData::Element { .. } = child.data {
+                        stack.push(Step::Enter(Rc::clone(child)));
+                    }
+                }
+            },
+            Step::Exit(handle) => {
+                let mut hasher = Fnv::default();
+                write_start(&mut hasher, &handle);
+                for child in handle.children.borrow().iter() {
+                    match child.data {
+                        NodeData::Element { .. } => hasher.write_u64(hashes[&(&**child as *const Node)]),
+                        NodeData::Text { ref contents } => write_str(&mut hasher, &contents.borrow()),
+                        _ => {},
+                    }
+                }
+                hasher.write_u8(CLOSE);
+                hashes.insert(&*handle as *const Node, hasher.finish());
+            },
+        }
+    }
+    hashes
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn body_hash(html: &str) -> u64 {
+        let dom = parse_document(RcDom::default(), Default::default()).one(html);
+        let html = dom.document.children.borrow().last().cloned().unwrap();
+        let body = html.children.borrow().last().cloned().unwrap();
+        subtree_hash(&body)
+    }
+
+    #[test]
+    fn test_subtree_hash() {
+        let a = body_hash("<div class='ad' id='x'><a href='/'>buy</a><!-- one --></div>");
+        let b = body_hash("<title>other</title><div id='x' class='ad'><a href='/'>buy</a><!-- two --></div>");
+        assert_eq!(a, b);
+        assert!(a != body_hash("<div class='ad' id='y'><a href='/'>buy</a></div>"));
+        assert!(a != body_hash("<div class='ad' id='x'><a href='/'>buy now</a></div>"));
+        assert!(a != body_hash("<div class='ad' id='x'><a href='/'></a>buy</div>"));
+    }
+
+    #[test]
+    fn test_element_hashes() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<div id=a><p>x</p><!-- c --><p>x</p></div><div id=b><p>y</p></div><div id=a><p>x</p><p>x</p></div>");
+        let hashes = element_hashes(&dom.document);
+        let html = dom.document.children.borrow().last().cloned().unwrap();
+        let body = html.children.borrow().last().cloned().unwrap();
+        let divs = body.children.borrow();
+        let hash = |h: &rcdom::Handle| hashes[&(&**h as *const Node)];
+        assert_eq!(hash(&divs[0]), hash(&divs[2]));
+        assert!(hash(&divs[0]) != hash(&divs[1]));
+        assert_eq!(subtree_hash(&divs[0]), subtree_hash(&divs[2]));
+    }
+}
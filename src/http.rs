@@ -0,0 +1,79 @@
+use bytes::{Buf, Bytes};
+use futures::{Stream, Poll, Async};
+use html5ever::tree_builder::TreeSink;
+use http_body::Body;
+
+use errors::Error;
+use fut::ParserFuture;
+
+/// HttpBodyStream adapts any `http_body::Body` into a `Stream` of `Bytes` so it can be fed
+/// into a `ParserFuture`. Each data buffer is flattened into a contiguous chunk, and errors
+/// from the body are wrapped in `Error::Stream`. Trailers are ignored.
+pub struct HttpBodyStream<B>(B);
+
+impl<B: Body> HttpBodyStream<B> {
+    pub fn new(body: B) -> Self {
+        HttpBodyStream(body)
+    }
+}
+
+impl<B: Body> Stream for HttpBodyStream<B> {
+    type Item = Bytes;
+    type Error = Error<B::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.0.poll_data().map_err(Error::Stream)? {
+            Async::Ready(Some(data)) => Ok(Async::Ready(Some(data.collect()))),
+            Async::Ready(None) => Ok(Async::Ready(None)),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<B, D> ParserFuture<HttpBodyStream<B>, Bytes, Error<B::Error>, D>
+    where B: Body,
+          D: TreeSink,
+{
+    /// from_http_body creates a ParserFuture that parses the data of any `http_body::Body`.
+    pub fn from_http_body(body: B, dom: D) -> Self {
+        ParserFuture::new(HttpBodyStream::new(body), dom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate http;
+    use std::collections::VecDeque;
+    use std::io::Cursor;
+
+    use futures::{Future, Poll, Async};
+    use http_body::Body;
+    use self::http::HeaderMap;
+
+    use super::*;
+    use rcdom::RcDom;
+    use ::canonicalize;
+
+    struct Chunks(VecDeque<Cursor<Bytes>>);
+
+    impl Body for Chunks {
+        type Data = Cursor<Bytes>;
+        type Error = ();
+
+        fn poll_data(&mut self) -> Poll<Option<Self::Data>, Self::Error> {
+            Ok(Async::Ready(self.0.pop_front()))
+        }
+
+        fn poll_trailers(&mut self) -> Poll<Option<HeaderMap>, Self::Error> {
+            Ok(Async::Ready(None))
+        }
+    }
+
+    #[test]
+    fn test_http_body() {
+        let chunks = vec!["<title>te", "st</title>"];
+        let body = Chunks(chunks.into_iter().map(|c| Cursor::new(c.into())).collect());
+        let dom = ParserFuture::from_http_body(body, RcDom::default()).wait().unwrap();
+        assert_eq!(canonicalize(&dom), "<html><head><title>test</title></head><body></body></html>");
+    }
+}
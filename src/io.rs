@@ -1,3 +1,5 @@
+use std::convert::Infallible;
+use std::fmt;
 use std::io;
 use html5ever::{
     parse_document,
@@ -7,6 +9,37 @@ use html5ever::{
     tendril::stream::Utf8LossyDecoder,
 };
 
+use rcdom::RcDom;
+use sink::{HookedSink, StripComments};
+
+/// FallibleOutput is implemented by the outputs of sinks that can fail when they are
+/// finished, like a sink validating the document it was given. Such a sink sets its
+/// `TreeSink::Output` to a `Result`, and `ParserSink::try_finish` hands it back as is.
+pub trait FallibleOutput {
+    type Ok;
+    type Error;
+
+    fn into_result(self) -> Result<Self::Ok, Self::Error>;
+}
+
+impl<T, E> FallibleOutput for Result<T, E> {
+    type Ok = T;
+    type Error = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+/// Finishing an RcDom never fails.
+impl FallibleOutput for RcDom {
+    type Ok = RcDom;
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<RcDom, Infallible> {
+        Ok(self)
+    }
+}
 
 /// ParserSink is a simple wrapper around a html5ever parser. It implements
 /// `std::io::Write` and allows you to stream data into it via the `std::io` primitives
@@ -24,19 +57,76 @@ impl<D> ParserSink<D> where D: TreeSink {
         }
     }
 
+    /// new_strip_comments creates a ParserSink that drops every comment while parsing, so
+    /// the resulting document is comment free without having to post-process it.
+    pub fn new_strip_comments(dom: D) -> ParserSink<HookedSink<D, StripComments>> {
+        ParserSink::new(HookedSink::new(dom, StripComments))
+    }
+
+    /// write_all_from feeds every chunk from the given iterator into the parser, in order.
+    /// All of the chunks are treated as one continuous document, so this can be used to
+    /// stitch a page together from several sources before calling `finish`. For streams
+    /// the same thing can be done by combining them with `Stream::chain` before handing
+    /// them to a `ParserFuture`.
+    pub fn write_all_from<I>(&mut self, chunks: I)
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>,
+    {
+        for chunk in chunks {
+            self.inner.process(chunk.as_ref().into());
+        }
+    }
+
+    /// dom gives access to the sink the parser is building into, so the tree built so far
+    /// can be looked at before calling `finish`. Everything written up to now is in it,
+    /// except for the incomplete tail described on `flush`.
+    pub fn dom(&self) -> &D {
+        &self.inner.inner_sink.tokenizer.sink.sink
+    }
+
     /// finish comsumes the ParserSink and returns the document structure completed by
     /// the inner parser.
     pub fn finish(self) -> D::Output {
         self.inner.finish()
     }
+
+    /// try_finish is `finish` for sinks whose output can fail, it returns the error the
+    /// sink reported instead of leaving it to the caller to unpack.
+    pub fn try_finish(self) -> Result<<D::Output as FallibleOutput>::Ok, <D::Output as FallibleOutput>::Error>
+        where D::Output: FallibleOutput,
+    {
+        self.inner.finish().into_result()
+    }
+}
+
+impl ParserSink<RcDom> {
+    /// error_count returns the number of parse errors the dom has recorded so far, which
+    /// is cheap enough to poll after every write for a running tally.
+    pub fn error_count(&self) -> usize {
+        self.dom().errors.len()
+    }
+}
+
+impl<D> fmt::Debug for ParserSink<D> where D: TreeSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ParserSink").finish()
+    }
 }
 
 impl<D> io::Write for ParserSink<D> where D: TreeSink {
+    /// write copies `buf` into a tendril and parses it. There is no way around that copy,
+    /// not even for a `Bytes` chunk holding valid utf-8: a tendril always owns its buffer
+    /// and can't share the allocation behind a `Bytes`, so there is no zero-copy
+    /// `write_bytes`. Writes of 8 bytes or less are stored inline and don't allocate.
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.inner.process(buf.into());
         Ok(buf.len())
     }
 
+    /// flush has nothing to do, html5ever runs the tokenizer and tree builder on each write
+    /// before it returns. The only input it holds back is what can't be processed without
+    /// more data: the bytes of a utf-8 character cut off at the end of a write, and a
+    /// token that isn't finished yet, like the `<di` of a tag split across writes.
     fn flush(&mut self) -> io::Result<()> {
         Ok(())
     }
@@ -45,15 +135,66 @@ impl<D> io::Write for ParserSink<D> where D: TreeSink {
 #[cfg(test)]
 mod tests {
     extern crate reqwest;
-    use html5ever::rcdom::RcDom;
     use std::io::Write;
 
     use super::*;
+    use rcdom::RcDom;
     const TEST_HTML: &'static str = "<html> <head> <title> test </title> </head> </html>";
     #[test]
     fn test_write() {
         let mut ps = ParserSink::new(RcDom::default());
         assert_eq!(ps.write(TEST_HTML.as_bytes()).unwrap(), TEST_HTML.len());
+        assert_eq!(format!("{:?}", ps), "ParserSink");
         ps.finish();
     }
+
+    #[test]
+    fn test_strip_comments() {
+        let mut ps = ParserSink::new_strip_comments(RcDom::default());
+        ps.write_all(b"<!-- top --><html><head><!--[if IE]><script></script><![endif]--></head>\
+                       <body><table><!-- fostered --><tr><td>a<!--b-->c</td></tr></table></body></html>").unwrap();
+        let dom = ps.finish();
+        assert_eq!(::canonicalize(&dom), "<html><head></head>\
+                   <body><table><tbody><tr><td>ac</td></tr></tbody></table></body></html>");
+    }
+
+    #[test]
+    fn test_dom_after_flush() {
+        let mut ps = ParserSink::new(RcDom::default());
+        ps.write_all(b"<p>one</p><p>tw").unwrap();
+        ps.flush().unwrap();
+        assert_eq!(::canonicalize(ps.dom()), "<html><head></head><body><p>one</p><p>tw</p></body></html>");
+        ps.write_all(b"o</p><di").unwrap();
+        ps.flush().unwrap();
+        assert_eq!(::canonicalize(ps.dom()), "<html><head></head><body><p>one</p><p>two</p></body></html>");
+    }
+
+    #[test]
+    fn test_try_finish() {
+        let mut ps = ParserSink::new(RcDom::default());
+        ps.write_all(TEST_HTML.as_bytes()).unwrap();
+        let dom = ps.try_finish().unwrap();
+        assert_eq!(::canonicalize(&dom), "<html><head> <title> test </title> </head> <body></body></html>");
+        let failed: Result<RcDom, &str> = Err("invalid");
+        assert_eq!(failed.into_result().err(), Some("invalid"));
+    }
+
+    #[test]
+    fn test_error_count() {
+        let mut ps = ParserSink::new(RcDom::default());
+        ps.write_all(b"<!DOCTYPE html><p>fine</p>").unwrap();
+        assert_eq!(ps.error_count(), 0);
+        ps.write_all(b"</span><b><i>misnested</b></i>").unwrap();
+        let count = ps.error_count();
+        assert!(count > 0);
+        assert_eq!(ps.finish().errors.len(), count);
+    }
+
+    #[test]
+    fn test_write_all_from() {
+        let mut ps = ParserSink::new(RcDom::default());
+        ps.write_all_from(vec!["<html><head><ti", "tle>test</title></head>", "<body></body></html>"]);
+        let dom = ps.finish();
+        assert_eq!(::canonicalize(&dom), "<html><head><title>test</title></head><body></body></html>");
+    }
 }
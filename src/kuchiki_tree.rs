@@ -0,0 +1,86 @@
+use kuchiki::{self, NodeRef, ExpandedName};
+
+use rcdom::{self, NodeData};
+
+fn convert(handle: &rcdom::Handle) -> NodeRef {
+    match handle.data {
+        NodeData::Document => NodeRef::new_document(),
+        NodeData::Doctype { ref name, ref public_id, ref system_id } => {
+            NodeRef::new_doctype(&**name, &**public_id, &**system_id)
+        },
+        NodeData::Text { ref contents } => NodeRef::new_text(&**contents.borrow()),
+        NodeData::Comment { ref contents } => NodeRef::new_comment(&**contents),
+        NodeData::Element { ref name, ref attrs, .. } => {
+            let attrs = attrs.borrow().iter().map(|attr| {
+                let name = ExpandedName::new(attr.name.ns.clone(), attr.name.local.clone());
+                (name, kuchiki::Attribute {
+                    prefix: attr.name.prefix.clone(),
+                    value: attr.value.to_string(),
+                })
+            }).collect::<Vec<_>>();
+            NodeRef::new_element(name.clone(), attrs)
+        },
+        NodeData::ProcessingInstruction { ref target, ref contents } => {
+            NodeRef::new_processing_instruction(&**target, &**contents)
+        },
+    }
+}
+
+/// to_kuchiki copies a parsed dom into a tree of kuchiki `NodeRef`s in a single pass, so
+/// it can be worked on with kuchiki's selectors and mutation methods. Element names and
+/// attributes keep their namespaces and prefixes, and the contents of `<template>`
+/// elements end up in kuchiki's template contents. The tree is walked without recursing.
+/// Only available with the `kuchiki` feature.
+pub fn to_kuchiki(dom: &rcdom::RcDom) -> NodeRef {
+    let root = convert(&dom.document);
+    let mut stack = vec![(dom.document.clone(), root.clone())];
+    while let Some((handle, node)) = stack.pop() {
+        // kuchiki creates an empty fragment for every template element, which is where
+        // html5ever's template contents go.
+        let template = match handle.data {
+            NodeData::Element { template_contents: Some(ref contents), .. } => {
+                let fragment = node.as_element()
+                    .and_then(|e| e.template_contents.clone())
+                    .expect("kuchiki creates template contents for template elements");
+                Some((contents.clone(), fragment))
+            },
+            _ => None,
+        };
+        stack.extend(template);
+        for child in handle.children.borrow().iter() {
+            let converted = convert(child);
+            node.append(converted.clone());
+            stack.push((child.clone(), converted));
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    #[test]
+    fn test_to_kuchiki() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<!DOCTYPE html><p class='x'>text<!--c--></p>\
+                  <svg xlink:href='#a'></svg><template><i>t</i></template>");
+        let doc = to_kuchiki(&dom);
+
+        let p = doc.select_first("p").unwrap();
+        assert_eq!(p.as_node().text_contents(), "text");
+        assert_eq!(p.attributes.borrow().get("class"), Some("x"));
+        let svg = doc.select_first("svg").unwrap();
+        assert_eq!(svg.name.ns, ns!(svg));
+        let attrs = svg.attributes.borrow();
+        let href = attrs.map.get(&ExpandedName::new(ns!(xlink), local_name!("href"))).unwrap();
+        assert_eq!(href.prefix, Some(namespace_prefix!("xlink")));
+        let template = doc.select_first("template").unwrap();
+        let contents = template.template_contents.as_ref().unwrap();
+        assert_eq!(contents.text_contents(), "t");
+    }
+}
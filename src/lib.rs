@@ -1,11 +1,97 @@
+extern crate bytes;
 extern crate futures;
+#[macro_use]
 extern crate html5ever;
+#[cfg(feature = "http-body")]
+extern crate http_body;
+#[cfg(feature = "kuchiki")]
+extern crate kuchiki;
+#[cfg(feature = "async-std")]
+extern crate async_std;
+#[cfg(feature = "serde_json")]
+extern crate serde_json;
 
+// Every module, tests included, reaches the rcdom types through this path instead of
+// naming `html5ever::rcdom` directly. Newer html5ever releases moved rcdom out into the
+// `markup5ever_rcdom` crate, switching over has to touch this import and the doc examples,
+// which are compiled as separate crates and can't see it.
+use html5ever::rcdom;
 
+pub mod errors;
+
+mod arc;
+#[cfg(feature = "async-std")]
+mod async_read;
+mod catch;
+mod closed;
 mod common;
+mod decode;
+mod diagnostics;
+mod diff;
+mod entities;
+mod extract;
+mod fragment;
+mod framework;
 mod fut;
+mod hash;
+#[cfg(feature = "http-body")]
+mod http;
 mod io;
+#[cfg(feature = "kuchiki")]
+mod kuchiki_tree;
+mod markdown;
+mod mutate;
+mod pool;
+mod progress;
+mod query;
+mod resume;
+mod script;
+mod select;
+mod serialize;
+mod sink;
+mod text;
+mod transform;
+mod validate;
 
-pub use fut::ParserFuture;
-pub use io::ParserSink;
-pub use common::{NodeStream, NodeIter};
+pub use fut::{ParserFuture, StrParserFuture, Guarded, Sniff, SniffChunk, Cooperative, Piece, parse_then_nodes};
+pub use fut::{InspectChunks, TryItems};
+pub use io::{ParserSink, FallibleOutput};
+pub use common::{NodeTraverser, NodeStream, NodeIter, TextTendrils, EventIter, DomEvent, Ancestors, ancestors};
+pub use common::{Siblings, next_siblings, prev_siblings, tag_name, is_element, head_and_body};
+pub use common::{TextWithContext, descendants_by_tag, root_element};
+pub use serialize::{serialize_html, outer_html, preview_html, canonicalize, canonicalize_with, CanonicalizeOpts};
+pub use serialize::{parse_and_write, pretty_serialize};
+pub use select::{Selector, count_matching, select, parse_select};
+pub use sink::{HookedSink, Hooks, StripComments, StripText, DepthLimit, MaxAttrs};
+pub use text::{text_content, normalized_text, word_count, block_text, text_to_markup_ratio};
+pub use text::{StyledSpan, to_spans};
+pub use markdown::to_markdown;
+pub use hash::subtree_hash;
+pub use diff::{DiffEntry, dom_diff};
+#[cfg(feature = "http-body")]
+pub use http::HttpBodyStream;
+pub use extract::{Extract, extract, ImageInfo, images, document_lang, base_href, get_element_by_id};
+pub use extract::{canonical_url, attrs, ScriptInfo, scripts};
+pub use extract::{get_elements_by_class, parse_table, jsonld, duplicate_ids, heading_outline};
+#[cfg(feature = "serde_json")]
+pub use extract::jsonld_values;
+pub use closed::{ClosedElementStream, SectionStream};
+pub use entities::{RawEntitiesFuture, RawEntities, EntityRef};
+pub use fragment::{FragmentBatcher, FragmentInfo, InsertionMode};
+pub use mutate::{remove_matching, rewrite_attrs, set_inner_html};
+pub use diagnostics::{DiagnosticFuture, Diagnostic};
+pub use arc::{ArcHandle, ArcNode, ArcNodeData, into_arc_tree};
+pub use decode::{Decode, ReplacementPolicy};
+pub use script::ScriptFuture;
+pub use progress::ProgressEvent;
+pub use query::NodeQuery;
+pub use pool::ParserPool;
+pub use resume::{ResumableSink, Checkpoint};
+pub use transform::TransformStream;
+pub use catch::CatchPanicFuture;
+pub use framework::{Framework, FrameworkMarker, default_markers, detect_framework, detect_framework_with};
+#[cfg(feature = "kuchiki")]
+pub use kuchiki_tree::to_kuchiki;
+#[cfg(feature = "async-std")]
+pub use async_read::{ReaderFuture, parse_reader};
+pub use validate::{TagBalanceValidator, TagBalance, TruncationFuture};
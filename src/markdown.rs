@@ -0,0 +1,366 @@
+use std::fmt::Write;
+use std::rc::Rc;
+
+use common::Step;
+use extract::attr;
+use rcdom::{self, NodeData};
+use text::text_content;
+
+/// Markdown collects the output of `to_markdown`. Line breaks and spaces are only written
+/// once more content follows them, so there is never any trailing whitespace to trim.
+#[derive(Default)]
+struct Markdown {
+    out: String,
+    // Newlines owed before the next content, 2 for a new paragraph.
+    lines: usize,
+    // A backslash is owed before the newlines, making it a hard line break.
+    hard: bool,
+    space: bool,
+    // What continuation lines inside the open list items are indented by, innermost last.
+    prefixes: Vec<String>,
+    // One entry for each open list, the number of the next item for ordered lists.
+    lists: Vec<Option<usize>>,
+    // The closing fence of each open `<code>` element.
+    code: Vec<String>,
+    // Something was written since the last line break, so text isn't at the start of a line.
+    mid_line: bool,
+}
+
+impl Markdown {
+    fn block(&mut self, lines: usize) {
+        self.lines = self.lines.max(lines);
+    }
+
+    fn flush(&mut self, space: bool) {
+        if self.lines > 0 {
+            if !self.out.is_empty() {
+                if self.hard {
+                    self.out.push('\\');
+                }
+                self.out.extend((0..self.lines).map(|_| '\n'));
+            }
+            self.out.push_str(self.prefixes.last().map_or("", |p| &p[..]));
+            self.mid_line = false;
+            self.lines = 0;
+            self.hard = false;
+            self.space = false;
+        } else if self.space && space {
+            if !self.out.is_empty() {
+                self.out.push(' ');
+            }
+            self.space = false;
+        }
+    }
+
+    /// open writes markup that starts something, after any whitespace owed.
+    fn open(&mut self, markup: &str) {
+        self.flush(true);
+        self.out.push_str(markup);
+        self.mid_line = true;
+    }
+
+    /// close writes markup that ends something, whitespace owed stays owed until after it.
+    fn close(&mut self, markup: &str) {
+        self.flush(false);
+        self.out.push_str(markup);
+        self.mid_line = true;
+    }
+
+    fn text(&mut self, contents: &str) {
+        if contents.starts_with(char::is_whitespace) {
+            self.space = true;
+        }
+        for (i, word) in contents.split_whitespace().enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+            self.flush(true);
+            if !self.code.is_empty() {
+                self.out.push_str(word);
+            } else if self.mid_line {
+                escape(&mut self.out, word);
+            } else {
+                escape_line_start(&mut self.out, word);
+            }
+            self.mid_line = true;
+        }
+        if contents.ends_with(char::is_whitespace) {
+            self.space = true;
+        }
+    }
+}
+
+fn escape(out: &mut String, text: &str) {
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// destination writes the url of a link or image. Spaces and control characters end a
+/// destination, so they are percent encoded, and parentheses that could end it early, as
+/// well as backslashes and angle brackets, are escaped.
+fn destination(out: &mut String, url: &str) {
+    for c in url.chars() {
+        if c.is_ascii_whitespace() || c.is_ascii_control() {
+            let _ = write!(out, "%{:02X}", c as u32);
+            continue;
+        }
+        if matches!(c, '\\' | '(' | ')' | '<' | '>') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// escape_line_start escapes a word at the start of a line, where `#`, `-`, `+` and `=`
+/// would start a heading, list or rule and a number followed by `.` or `)` an ordered list.
+fn escape_line_start(out: &mut String, word: &str) {
+    if word.starts_with(['#', '-', '+', '=']) {
+        out.push('\\');
+        escape(out, word);
+        return;
+    }
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    if digits > 0 && word[digits..].starts_with(['.', ')']) {
+        out.push_str(&word[..digits]);
+        out.push('\\');
+        escape(out, &word[digits..]);
+        return;
+    }
+    escape(out, word);
+}
+
+/// fence returns a run of backticks longer than any run inside `text`, and at least `min`
+/// long, so the text can't end the code it is wrapped in.
+fn fence(text: &str, min: usize) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(min.max(longest + 1))
+}
+
+fn heading_level(local: &str) -> Option<usize> {
+    match local {
+        "h1" => Some(1),
+        "h2" => Some(2),
+        "h3" => Some(3),
+        "h4" => Some(4),
+        "h5" => Some(5),
+        "h6" => Some(6),
+        _ => None,
+    }
+}
+
+fn is_block(local: &str) -> bool {
+    matches!(local,
+        "address" | "article" | "aside" | "blockquote" | "body" | "dd" | "details" | "div" |
+        "dl" | "dt" | "fieldset" | "figcaption" | "figure" | "footer" | "form" | "header" |
+        "main" | "nav" | "p" | "section" | "summary" | "table")
+}
+
+/// to_markdown converts the body of a document into markdown, for content pipelines that
+/// store scraped pages as text. It is a basic conversion and supports:
+///
+/// * `<h1>` to `<h6>` as `#` headings
+/// * paragraphs and other block elements like `<div>`, separated by blank lines
+/// * `<ul>` and `<ol>` lists, nested ones indented below their item
+/// * `<b>` and `<strong>` as `**bold**`, `<i>` and `<em>` as `*italic*`
+/// * `<code>` as inline code and `<pre>` as a fenced code block, both kept verbatim
+/// * `<a href>` as links, `<img src>` as images, `<br>` as a hard line break and `<hr>` as
+///   a horizontal rule
+///
+/// Anything else degrades to its text, with whitespace collapsed and table rows on
+/// lines of their own. The head, scripts and styles are left out. Characters markdown
+/// would read as markup are escaped with a backslash, except inside code. Urls are escaped
+/// too, so a `)` or a space in them doesn't end the link early.
+pub fn to_markdown(dom: &rcdom::RcDom) -> String {
+    let mut md = Markdown::default();
+    let mut stack = vec![Step::Enter(Rc::clone(&dom.document))];
+    while let Some(step) = stack.pop() {
+        let (handle, enter) = match step {
+            Step::Enter(handle) => (handle, true),
+            Step::Exit(handle) => (handle, false),
+        };
+        let local = match handle.data {
+            NodeData::Element { ref name, .. } => name.local.clone(),
+            NodeData::Text { ref contents } => {
+                md.text(&contents.borrow());
+                continue;
+            },
+            NodeData::Document => {
+                for child in handle.children.borrow().iter().rev() {
+                    stack.push(Step::Enter(Rc::clone(child)));
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        let local = &*local;
+        if !enter {
+            match local {
+                "b" | "strong" => md.close("**"),
+                "i" | "em" => md.close("*"),
+                "code" => {
+                    let end = md.code.pop().unwrap_or_default();
+                    md.close(&end);
+                },
+                "a" => if let Some(href) = attr(&handle, "href") {
+                    let mut link = String::from("](");
+                    destination(&mut link, &href);
+                    link.push(')');
+                    md.close(&link);
+                },
+                "ul" | "ol" => {
+                    md.lists.pop();
+                    md.block(2);
+                },
+                "li" => {
+                    md.prefixes.pop();
+                    md.block(1);
+                },
+                "tr" => md.block(1),
+                _ if heading_level(local).is_some() || is_block(local) => md.block(2),
+                _ => {},
+            }
+            continue;
+        }
+        match local {
+            "head" | "script" | "style" | "template" => continue,
+            "pre" => {
+                md.block(2);
+                let code = text_content(&handle);
+                let fence = fence(&code, 3);
+                md.open(&fence);
+                md.out.push('\n');
+                md.out.push_str(code.trim_end_matches('\n'));
+                md.out.push('\n');
+                md.out.push_str(&fence);
+                md.block(2);
+                continue;
+            },
+            "br" => {
+                md.block(1);
+                md.hard = true;
+            },
+            "hr" => {
+                md.block(2);
+                md.open("---");
+                md.block(2);
+            },
+            "img" => if let Some(src) = attr(&handle, "src") {
+                let alt = attr(&handle, "alt").unwrap_or_default();
+                let mut image = String::from("![");
+                escape(&mut image, &alt);
+                image.push_str("](");
+                destination(&mut image, &src);
+                image.push(')');
+                md.open(&image);
+            },
+            "b" | "strong" => md.open("**"),
+            "i" | "em" => md.open("*"),
+            "code" => {
+                // A space keeps a backtick at either end from joining the fence, markdown
+                // strips one on each side again.
+                let code = text_content(&handle);
+                let mut start = fence(&code, 1);
+                let mut end = start.clone();
+                if code.starts_with('`') || code.ends_with('`') {
+                    start.push(' ');
+                    end.insert(0, ' ');
+                }
+                md.open(&start);
+                md.code.push(end);
+            },
+            "a" => if attr(&handle, "href").is_some() {
+                md.open("[");
+            },
+            "ul" => {
+                md.block(if md.lists.is_empty() { 2 } else { 1 });
+                md.lists.push(None);
+            },
+            "ol" => {
+                md.block(if md.lists.is_empty() { 2 } else { 1 });
+                md.lists.push(Some(1));
+            },
+            "li" => {
+                let marker = match md.lists.last_mut() {
+                    Some(&mut Some(ref mut n)) => {
+                        *n += 1;
+                        format!("{}. ", *n - 1)
+                    },
+                    _ => "- ".to_owned(),
+                };
+                md.block(1);
+                md.open(&marker);
+                md.mid_line = false;
+                let prefix = md.prefixes.last().cloned().unwrap_or_default();
+                md.prefixes.push(prefix + &" ".repeat(marker.len()));
+            },
+            "td" | "th" => md.space = true,
+            "tr" => md.block(1),
+            _ => if let Some(level) = heading_level(local) {
+                md.block(2);
+                md.open(&format!("{} ", "#".repeat(level)));
+            } else if is_block(local) {
+                md.block(2);
+            },
+        }
+        stack.push(Step::Exit(Rc::clone(&handle)));
+        for child in handle.children.borrow().iter().rev() {
+            stack.push(Step::Enter(Rc::clone(child)));
+        }
+    }
+    md.out
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn markdown(html: &str) -> String {
+        to_markdown(&parse_document(RcDom::default(), Default::default()).one(html))
+    }
+
+    #[test]
+    fn test_to_markdown() {
+        assert_eq!(markdown("<title>skipped</title><h2>A  <em>title</em></h2>\
+            <p>Some <b>bold </b>text with a <a href='/x'>link</a>,<br>and *stars*.</p>\
+            <pre>let x = 1;\n  y();\n</pre><p>Run <code>a*b</code> <img src='i.png' alt='pic'></p>\
+            <script>no()</script><hr><div>plain</div>"),
+            "## A *title*\n\n\
+             Some **bold** text with a [link](/x),\\\nand \\*stars\\*.\n\n\
+             ```\nlet x = 1;\n  y();\n```\n\n\
+             Run `a*b` ![pic](i.png)\n\n\
+             ---\n\n\
+             plain");
+    }
+
+    #[test]
+    fn test_to_markdown_lists() {
+        assert_eq!(markdown("<p>before</p><ul><li>one<li>two<ol><li>a<li>b</li></ol></li></ul>\
+            <table><tr><td>c1<td>c2<tr><td>c3</table>"),
+            "before\n\n- one\n- two\n  1. a\n  2. b\n\nc1 c2\nc3");
+    }
+
+    #[test]
+    fn test_to_markdown_line_starts() {
+        assert_eq!(markdown("<p># not heading</p><p>- not list</p><p>1. not list</p>\
+            <p>a<br>+ b = 2) c</p><ul><li>#x</li></ul><p>Use <code>a`b</code> or <code>`c</code></p>\
+            <pre>```\n</pre>"),
+            "\\# not heading\n\n\\- not list\n\n1\\. not list\n\n\
+             a\\\n\\+ b = 2) c\n\n- \\#x\n\n\
+             Use ``a`b`` or `` `c ``\n\n\
+             ````\n```\n````");
+    }
+
+    #[test]
+    fn test_to_markdown_destinations() {
+        assert_eq!(markdown("<a href='x) y'>link</a> <img src='a\\b(1).png' alt=pic>"),
+            "[link](x\\)%20y) ![pic](a\\\\b\\(1\\).png)");
+    }
+}
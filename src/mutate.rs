@@ -0,0 +1,139 @@
+use std::mem;
+use std::rc::Rc;
+
+use html5ever::{parse_fragment, Attribute, QualName};
+use html5ever::tendril::TendrilSink;
+
+use common::{ancestors, elements};
+use errors::SelectorError;
+use rcdom::{self, NodeData, RcDom};
+use select::select;
+
+/// detach removes a node from its parent's children and clears its parent reference. The
+/// node and its subtree stay intact for anyone still holding on to them.
+pub(crate) fn detach(handle: &rcdom::Handle) {
+    let parent = match handle.parent.take().and_then(|weak| weak.upgrade()) {
+        Some(parent) => parent,
+        None => return,
+    };
+    parent.children.borrow_mut().retain(|child| !Rc::ptr_eq(child, handle));
+}
+
+/// remove_matching detaches every element matching `selector` from the tree, so walking
+/// or serializing the dom afterwards skips them and everything inside them. This mutates
+/// the dom in place, every handle into the same tree sees the change. It returns the
+/// number of elements removed, matches nested inside an element that was already removed
+/// are not counted.
+pub fn remove_matching(dom: &rcdom::RcDom, selector: &str) -> Result<usize, SelectorError> {
+    let mut removed = 0;
+    for handle in select(dom, selector)? {
+        if ancestors(&handle).any(|h| Rc::ptr_eq(&h, &dom.document)) {
+            detach(&handle);
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// rewrite_attrs calls `f` with the name and attributes of every element in the dom, in
+/// document order, letting it add, change or remove attributes in place. Like
+/// `remove_matching` this mutates the shared tree. The attributes stay borrowed while `f`
+/// runs, so `f` must not look at the attributes of other elements through the dom.
+pub fn rewrite_attrs<F>(dom: &rcdom::RcDom, mut f: F)
+    where F: FnMut(&QualName, &mut Vec<Attribute>),
+{
+    for handle in elements(&dom.document) {
+        if let NodeData::Element { ref name, ref attrs, .. } = handle.data {
+            f(name, &mut attrs.borrow_mut());
+        }
+    }
+}
+
+/// set_inner_html replaces the children of an element with `html`, parsed as a fragment
+/// in the context of the element like assigning `innerHTML` does in a browser, so `<tr>`
+/// markup set on a `<tbody>` or text set on a `<textarea>` comes out right. The old
+/// children are detached and the new ones are parented to the element. For a `<template>`
+/// the contents fragment is replaced instead. Nodes other than elements are left alone.
+pub fn set_inner_html(handle: &rcdom::Handle, html: &str) {
+    let (name, attrs, target) = match handle.data {
+        NodeData::Element { ref name, ref attrs, ref template_contents, .. } => {
+            let target = template_contents.clone().unwrap_or_else(|| Rc::clone(handle));
+            (name.clone(), attrs.borrow().clone(), target)
+        },
+        _ => return,
+    };
+    let fragment = parse_fragment(RcDom::default(), Default::default(), name, attrs).one(html);
+    // The parsed nodes end up below an `<html>` element standing in for the context.
+    let root = match fragment.document.children.borrow().first() {
+        Some(root) => Rc::clone(root),
+        None => return,
+    };
+    let old = mem::take(&mut *target.children.borrow_mut());
+    for child in old {
+        child.parent.set(None);
+    }
+    let new = mem::take(&mut *root.children.borrow_mut());
+    for child in &new {
+        child.parent.set(Some(Rc::downgrade(&target)));
+    }
+    *target.children.borrow_mut() = new;
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+    use common::parent;
+    use serialize::canonicalize;
+
+    #[test]
+    fn test_remove_matching() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<div class='ad'><div class='ad'>nested</div></div><p>keep<script>x</script></p>");
+        let ads = select(&dom, ".ad").unwrap();
+        assert_eq!(remove_matching(&dom, ".ad, script").unwrap(), 2);
+        assert_eq!(canonicalize(&dom), "<html><head></head><body><p>keep</p></body></html>");
+        // Nested matches stay attached to their removed ancestor.
+        assert!(parent(&ads[0]).is_none());
+        assert!(Rc::ptr_eq(&parent(&ads[1]).unwrap(), &ads[0]));
+        assert_eq!(remove_matching(&dom, ".ad").unwrap(), 0);
+        assert!(remove_matching(&dom, "p:empty").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_attrs() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<a href='javascript:alert(1)' onclick='x()' title='t'>a</a><p onload=y>b</p>");
+        rewrite_attrs(&dom, |name, attrs| {
+            attrs.retain(|a| !a.name.local.starts_with("on"));
+            if &*name.local == "a" {
+                for attr in attrs.iter_mut().filter(|a| &*a.name.local == "href") {
+                    if attr.value.starts_with("javascript:") {
+                        attr.value = "#".into();
+                    }
+                }
+            }
+        });
+        assert_eq!(canonicalize(&dom),
+                   "<html><head></head><body><a href=\"#\" title=\"t\">a</a><p>b</p></body></html>");
+    }
+
+    #[test]
+    fn test_set_inner_html() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<table><tbody id=t><tr><td>old</td></tr></tbody></table><textarea id=a></textarea>");
+        let old = elements(&dom.document).find(|h| ::tag_name(h) == Some(local_name!("tr"))).unwrap();
+        let tbody = ::get_element_by_id(&dom, "t").unwrap();
+        set_inner_html(&tbody, "<tr><td>a<td>b");
+        set_inner_html(&::get_element_by_id(&dom, "a").unwrap(), "<b>not markup</b>");
+        assert_eq!(canonicalize(&dom), "<html><head></head><body><table><tbody id=\"t\">\
+            <tr><td>a</td><td>b</td></tr></tbody></table>\
+            <textarea id=\"a\">&lt;b&gt;not markup&lt;/b&gt;</textarea></body></html>");
+        assert!(parent(&old).is_none());
+        let tr = Rc::clone(&tbody.children.borrow()[0]);
+        assert!(Rc::ptr_eq(&parent(&tr).unwrap(), &tbody));
+    }
+}
@@ -0,0 +1,80 @@
+use futures::Stream;
+use html5ever::ParseOpts;
+use html5ever::tree_builder::TreeSink;
+
+use fut::ParserFuture;
+
+/// ParserPool stamps out `ParserFuture`s that all share the same `ParseOpts`, each parsing
+/// into a fresh sink from `new_sink`. The futures are independent of each other and of
+/// the pool, so any number of them can run at the same time, which is what a crawler
+/// parsing many pages wants. Despite the name nothing is pooled between parses, every
+/// future builds its own parser and buffers.
+/// # Examples
+/// ```rust
+/// extern crate futures;
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use futures::{future, stream, Future};
+/// use html5ever::rcdom::RcDom;
+/// use html5ever_stream::{ParserPool, canonicalize};
+///
+/// let pool = ParserPool::new(RcDom::default, Default::default());
+/// let pages = vec![vec!["<p>one"], vec!["<p>tw", "o"]];
+/// let doms = future::join_all(pages.into_iter()
+///     .map(|chunks| pool.parse(stream::iter_ok::<_, ()>(chunks))))
+///     .wait()
+///     .unwrap();
+/// assert_eq!(canonicalize(&doms[1]), "<html><head></head><body><p>two</p></body></html>");
+/// ```
+pub struct ParserPool<F> {
+    new_sink: F,
+    opts: ParseOpts,
+}
+
+impl<F, D> ParserPool<F>
+    where F: Fn() -> D,
+          D: TreeSink,
+{
+    pub fn new(new_sink: F, opts: ParseOpts) -> Self {
+        ParserPool {
+            new_sink,
+            opts,
+        }
+    }
+
+    /// parse creates a `ParserFuture` for the given stream using the pool's options.
+    pub fn parse<S, C, E>(&self, s: S) -> ParserFuture<S, C, E, D>
+        where S: Stream<Item=C, Error=E>,
+              C: AsRef<[u8]>,
+    {
+        ParserFuture::with_opts(s, (self.new_sink)(), self.opts.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+    use html5ever::ParseOpts;
+    use html5ever::tree_builder::TreeBuilderOpts;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    #[test]
+    fn test_shared_opts() {
+        let opts = ParseOpts {
+            tree_builder: TreeBuilderOpts {
+                scripting_enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let pool = ParserPool::new(RcDom::default, opts);
+        for _ in 0..2 {
+            let chunks = vec!["<body><noscript><p>shown</p></noscript>"];
+            let dom = pool.parse(stream::iter_ok::<_, ()>(chunks)).wait().unwrap();
+            assert_eq!(::select(&dom, "noscript > p").unwrap().len(), 1);
+        }
+    }
+}
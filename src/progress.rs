@@ -0,0 +1,65 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use html5ever::tree_builder::TreeSink;
+
+use fut::ParserFuture;
+
+/// ProgressEvent is passed to the callback of `ParserFuture::with_progress` as parsing
+/// moves along.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressEvent {
+    /// A chunk of this many bytes arrived and is about to be parsed.
+    ChunkReceived { bytes: usize },
+    /// The input ended and the document has been completed.
+    Finished,
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    /// with_progress parses a stream like `ParserFuture` while calling `callback` with a
+    /// `ProgressEvent` every time a chunk arrives and once the document is done. Handy for
+    /// driving a progress indicator or logging where a slow parse spends its time. It is
+    /// built on `inspect_chunks`, so the callback runs inside `poll`, right as things
+    /// happen, and should be quick.
+    pub fn with_progress<F>(s: S, dom: D, callback: F) -> impl Future<Item=D::Output, Error=E>
+        where F: FnMut(ProgressEvent),
+    {
+        let callback = Rc::new(RefCell::new(callback));
+        let chunks = Rc::clone(&callback);
+        ParserFuture::inspect_chunks(s, dom, move |chunk| {
+            (*chunks.borrow_mut())(ProgressEvent::ChunkReceived { bytes: chunk.len() })
+        }).map(move |dom| {
+            (*callback.borrow_mut())(ProgressEvent::Finished);
+            dom
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::RcDom;
+
+    #[test]
+    fn test_progress() {
+        let mut events = Vec::new();
+        let chunks = vec!["<p>one", "</p>", "<p>two</p>"];
+        ParserFuture::with_progress(stream::iter_ok::<_, ()>(chunks), RcDom::default(),
+                                    |event| events.push(event))
+            .wait()
+            .unwrap();
+        assert_eq!(events, vec![
+            ProgressEvent::ChunkReceived { bytes: 6 },
+            ProgressEvent::ChunkReceived { bytes: 4 },
+            ProgressEvent::ChunkReceived { bytes: 10 },
+            ProgressEvent::Finished,
+        ]);
+    }
+}
@@ -0,0 +1,110 @@
+use html5ever::LocalName;
+
+use common::{is_element, NodeStream, NodeTraverser, Predicate};
+use extract::attr;
+use rcdom::{self, NodeData};
+
+/// NodeQuery builds up a filter on the nodes of a document one condition at a time and
+/// turns it into a `NodeStream` yielding only the nodes that pass all of them. Nodes come
+/// out in the same breadth first order as an unfiltered `NodeStream`.
+/// # Examples
+/// ```rust
+/// extern crate futures;
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use futures::{Future, Stream};
+/// use html5ever::parse_document;
+/// use html5ever::rcdom::RcDom;
+/// use html5ever::tendril::TendrilSink;
+/// use html5ever_stream::NodeQuery;
+///
+/// let dom = parse_document(RcDom::default(), Default::default())
+///     .one("<a href='/a' rel='nofollow'>a</a><a href='/b'>b</a>");
+/// let links = NodeQuery::new(&dom).elements().tag("a").attr("rel", Some("nofollow"))
+///     .stream()
+///     .collect()
+///     .wait()
+///     .unwrap();
+/// assert_eq!(links.len(), 1);
+/// ```
+pub struct NodeQuery {
+    traverser: NodeTraverser,
+    predicates: Vec<Predicate>,
+}
+
+impl NodeQuery {
+    pub fn new(dom: &rcdom::RcDom) -> Self {
+        NodeQuery {
+            traverser: NodeTraverser::new(dom),
+            predicates: Vec::new(),
+        }
+    }
+
+    /// filter keeps only the nodes `predicate` accepts.
+    pub fn filter<F>(mut self, predicate: F) -> Self
+        where F: Fn(&rcdom::Handle) -> bool + 'static,
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// elements keeps only element nodes.
+    pub fn elements(self) -> Self {
+        self.filter(is_element)
+    }
+
+    /// tag keeps only elements with the given local name, in any namespace.
+    pub fn tag(self, tag: &str) -> Self {
+        let tag = LocalName::from(tag);
+        self.filter(move |handle| match handle.data {
+            NodeData::Element { ref name, .. } => name.local == tag,
+            _ => false,
+        })
+    }
+
+    /// attr keeps only elements that have the named attribute. With a value the attribute
+    /// also has to be exactly equal to it.
+    pub fn attr(self, name: &str, value: Option<&str>) -> Self {
+        let name = name.to_owned();
+        let value = value.map(str::to_owned);
+        self.filter(move |handle| match (attr(handle, &name), value.as_ref()) {
+            (Some(found), Some(expected)) => found == *expected,
+            (found, None) => found.is_some(),
+            (None, _) => false,
+        })
+    }
+
+    /// stream turns the query into a `NodeStream` over the matching nodes.
+    pub fn stream(self) -> NodeStream {
+        let predicates = self.predicates;
+        let traverser = self.traverser.filtered(Box::new(move |handle| {
+            predicates.iter().all(|p| p(handle))
+        }));
+        NodeStream::from_traverser(traverser)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+    use text::text_content;
+
+    #[test]
+    fn test_node_query() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<a rel='nofollow'>1</a><div rel='nofollow'>2</div><a>3</a><a rel='me'>4</a>");
+        let texts = |query: NodeQuery| -> Vec<String> {
+            query.stream().into_vec().iter().map(text_content).collect()
+        };
+        assert_eq!(texts(NodeQuery::new(&dom).tag("a")), vec!["1", "3", "4"]);
+        assert_eq!(texts(NodeQuery::new(&dom).attr("rel", None)), vec!["1", "2", "4"]);
+        assert_eq!(texts(NodeQuery::new(&dom).tag("a").attr("rel", Some("nofollow"))), vec!["1"]);
+        assert_eq!(NodeQuery::new(&dom).elements().stream().into_vec().len(), 7);
+        assert_eq!(NodeQuery::new(&dom).stream().into_vec().len(), 12);
+    }
+}
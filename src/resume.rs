@@ -0,0 +1,116 @@
+use std::io;
+
+use html5ever::tree_builder::TreeSink;
+
+use io::ParserSink;
+
+/// Checkpoint is the saved state of a `ResumableSink`. html5ever's tokenizer and tree
+/// builder can't be serialized, so instead of parser state a checkpoint holds every byte
+/// fed so far, which can be written out and read back in as is.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Checkpoint {
+    bytes: Vec<u8>,
+}
+
+impl Checkpoint {
+    /// as_bytes returns the input recorded up to the checkpoint, for persisting it.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+impl From<Vec<u8>> for Checkpoint {
+    fn from(bytes: Vec<u8>) -> Self {
+        Checkpoint { bytes }
+    }
+}
+
+/// ResumableSink is a `ParserSink` that can be checkpointed and picked up again later,
+/// for example by a crawler that persists its work before shutting down.
+///
+/// A parse can't actually be frozen and thawed, so the sink keeps a copy of all of its
+/// input and `resume` replays that input into a fresh parser before carrying on. The
+/// resumed document ends up exactly as if the parse had never stopped, but holding on to
+/// the input costs as much memory again as the input itself, and resuming costs as much
+/// time as having parsed it up to the checkpoint.
+pub struct ResumableSink<D: TreeSink> {
+    sink: ParserSink<D>,
+    input: Vec<u8>,
+}
+
+impl<D> ResumableSink<D> where D: TreeSink {
+    pub fn new(dom: D) -> Self {
+        ResumableSink {
+            sink: ParserSink::new(dom),
+            input: Vec::new(),
+        }
+    }
+
+    /// resume parses the input saved in `checkpoint` into `dom`, returning a sink that
+    /// continues where the checkpointed one left off.
+    pub fn resume(checkpoint: Checkpoint, dom: D) -> Self {
+        let mut sink = ParserSink::new(dom);
+        sink.write_all_from(Some(&checkpoint.bytes));
+        ResumableSink {
+            sink,
+            input: checkpoint.bytes,
+        }
+    }
+
+    /// feed parses the next chunk of input, remembering it for the next checkpoint.
+    pub fn feed(&mut self, chunk: &[u8]) {
+        self.input.extend_from_slice(chunk);
+        self.sink.write_all_from(Some(chunk));
+    }
+
+    /// checkpoint saves everything fed so far. The sink can keep going afterwards.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint { bytes: self.input.clone() }
+    }
+
+    /// dom gives access to the partial document built so far, like `ParserSink::dom`.
+    pub fn dom(&self) -> &D {
+        self.sink.dom()
+    }
+
+    pub fn finish(self) -> D::Output {
+        self.sink.finish()
+    }
+}
+
+impl<D> io::Write for ResumableSink<D> where D: TreeSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.feed(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rcdom::RcDom;
+    use serialize::canonicalize;
+
+    #[test]
+    fn test_resume() {
+        let mut sink = ResumableSink::new(RcDom::default());
+        sink.feed(b"<p>one</p><p>tw");
+        let saved = sink.checkpoint().into_bytes();
+        drop(sink);
+
+        let mut sink = ResumableSink::resume(Checkpoint::from(saved), RcDom::default());
+        sink.feed(b"o</p>");
+        assert_eq!(sink.checkpoint().as_bytes(), &b"<p>one</p><p>two</p>"[..]);
+        assert_eq!(canonicalize(&sink.finish()),
+                   "<html><head></head><body><p>one</p><p>two</p></body></html>");
+    }
+}
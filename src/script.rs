@@ -0,0 +1,128 @@
+use std::marker::PhantomData;
+
+use futures::{Future, Stream, Poll, Async};
+use html5ever::{parse_document, Parser};
+use html5ever::tendril::TendrilSink;
+use html5ever::tokenizer::TokenizerResult;
+use html5ever::tree_builder::TreeSink;
+
+use decode::{Decode, ReplacementPolicy};
+use fut::{Budget, ParserFuture};
+use rcdom;
+use text::text_content;
+
+/// ScriptFuture parses a stream like `ParserFuture`, but hands the text of every
+/// `<script>` element to a handler as soon as its end tag has been parsed. Markup returned
+/// by the handler is parsed right after the script, before the rest of the input, which is
+/// how `document.write` behaves in a browser. It is created by
+/// `ParserFuture::with_script_handler`.
+///
+/// The handler runs synchronously inside `poll`, so a slow script stalls the future. It
+/// can't look at the dom while it runs, the parser holds on to it until the future
+/// resolves. Markup it returns is parsed with the same rules as the rest of the input, so
+/// it can contain scripts of its own, which are passed to the handler in turn. A handler
+/// that keeps writing scripts for its own output never lets the parse finish. Scripts
+/// whose end tag never arrives are not run.
+#[must_use = "futures do nothing unless polled"]
+pub struct ScriptFuture<S, E, D, F>
+    where D: TreeSink,
+{
+    stream: Decode<S>,
+    parser: Option<Parser<D>>,
+    handler: F,
+    err_type: PhantomData<E>,
+}
+
+impl<S, E, D, F> ScriptFuture<S, E, D, F>
+    where D: TreeSink<Handle=rcdom::Handle>,
+          F: FnMut(&str) -> Option<String>,
+{
+    /// feed runs the parser over a decoded chunk, stopping after every script to run it.
+    fn feed(&mut self, chunk: String) {
+        let parser = self.parser.as_mut().expect("polled ScriptFuture after completion");
+        parser.input_buffer.push_back(chunk.into());
+        while let TokenizerResult::Script(script) = parser.tokenizer.feed(&mut parser.input_buffer) {
+            if let Some(markup) = (self.handler)(&text_content(&script)) {
+                parser.input_buffer.push_front(markup.into());
+            }
+        }
+    }
+}
+
+impl<S, C, E, D, F> Future for ScriptFuture<S, E, D, F>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+          F: FnMut(&str) -> Option<String>,
+{
+    type Item = D::Output;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    self.feed(chunk);
+                    if budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Async::Ready(None) => {
+                    let parser = self.parser.take().expect("polled ScriptFuture after completion");
+                    return Ok(Async::Ready(parser.finish()));
+                },
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+{
+    /// with_script_handler creates a `ScriptFuture`, which calls `handler` with the text of
+    /// each script once it has been parsed and parses whatever markup the handler returns
+    /// in its place. Parsing happens with scripting enabled, so `<noscript>` contents are
+    /// kept as raw text the way a browser running scripts would see them.
+    pub fn with_script_handler<F>(s: S, dom: D, handler: F) -> ScriptFuture<S, E, D, F>
+        where F: FnMut(&str) -> Option<String>,
+    {
+        ScriptFuture {
+            stream: Decode::new(s, ReplacementPolicy::Default),
+            parser: Some(parse_document(dom, Default::default())),
+            handler,
+            err_type: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::RcDom;
+    use serialize::canonicalize;
+
+    #[test]
+    fn test_script_handler() {
+        let chunks = vec!["<p>a</p><script>write('<b>b</b>')</scr", "ipt><p>c</p><script>nothing</script>"];
+        let mut seen = Vec::new();
+        let dom = ParserFuture::with_script_handler(stream::iter_ok::<_, ()>(chunks), RcDom::default(),
+            |script| {
+                seen.push(script.to_owned());
+                if script.starts_with("write") {
+                    Some("<i>nested</i><script>nothing</script>".to_owned())
+                } else {
+                    None
+                }
+            }).wait().unwrap();
+        assert_eq!(seen, vec!["write('<b>b</b>')", "nothing", "nothing"]);
+        assert_eq!(canonicalize(&dom), "<html><head></head><body><p>a</p>\
+            <script>write('<b>b</b>')</script><i>nested</i><script>nothing</script>\
+            <p>c</p><script>nothing</script></body></html>");
+    }
+}
@@ -0,0 +1,423 @@
+use std::str::FromStr;
+
+use futures::{Future, Stream};
+use html5ever::LocalName;
+use html5ever::tree_builder::TreeSink;
+
+use common::{ancestors, elements, parent, NodeIter};
+use errors::SelectorError;
+use extract::in_class_list;
+use fut::ParserFuture;
+use rcdom::{self, NodeData};
+
+enum AttrOp {
+    Equals,
+    Includes,
+    DashMatch,
+    Prefix,
+    Suffix,
+    Substring,
+}
+
+impl AttrOp {
+    fn matches(&self, value: &str, expected: &str) -> bool {
+        match *self {
+            AttrOp::Equals => value == expected,
+            AttrOp::Includes => value.split_whitespace().any(|v| v == expected),
+            AttrOp::DashMatch => {
+                value == expected
+                    || (value.starts_with(expected) && value[expected.len()..].starts_with('-'))
+            },
+            AttrOp::Prefix => !expected.is_empty() && value.starts_with(expected),
+            AttrOp::Suffix => !expected.is_empty() && value.ends_with(expected),
+            AttrOp::Substring => !expected.is_empty() && value.contains(expected),
+        }
+    }
+}
+
+/// Name is a tag or attribute name from a selector. Html names are matched case
+/// insensitively, foreign ones like svg's `foreignObject` or `viewBox` only as written.
+struct Name {
+    lower: LocalName,
+    written: LocalName,
+}
+
+impl Name {
+    fn new(written: &str) -> Self {
+        Name {
+            lower: LocalName::from(&*written.to_ascii_lowercase()),
+            written: LocalName::from(written),
+        }
+    }
+
+    /// on picks the name to compare with on an element, depending on its namespace.
+    fn on(&self, html: bool) -> &LocalName {
+        if html { &self.lower } else { &self.written }
+    }
+}
+
+enum Simple {
+    Tag(Name),
+    Id(String),
+    Class(String),
+    Attr(Name, Option<(AttrOp, String)>),
+}
+
+/// Compound is a run of simple selectors with no combinators between them, like
+/// `a.external[href]`. The universal selector is an empty compound.
+struct Compound(Vec<Simple>);
+
+impl Compound {
+    fn matches(&self, handle: &rcdom::Handle) -> bool {
+        let (name, attrs) = match handle.data {
+            NodeData::Element { ref name, ref attrs, .. } => (name, attrs.borrow()),
+            _ => return false,
+        };
+        let html = name.ns == ns!(html);
+        let attr = |local: &str| {
+            attrs.iter().find(|a| &*a.name.local == local).map(|a| &*a.value)
+        };
+        self.0.iter().all(|simple| match *simple {
+            Simple::Tag(ref local) => name.local == *local.on(html),
+            Simple::Id(ref id) => attr("id") == Some(id),
+            Simple::Class(ref class) => {
+                attr("class").is_some_and(|c| in_class_list(c, class))
+            },
+            Simple::Attr(ref local, None) => attr(local.on(html)).is_some(),
+            Simple::Attr(ref local, Some((ref op, ref expected))) => {
+                attr(local.on(html)).is_some_and(|v| op.matches(v, expected))
+            },
+        })
+    }
+}
+
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// Complex is a chain of compounds joined by combinators, stored left to right. The
+/// combinator at index `i` sits between `compounds[i]` and `compounds[i + 1]`.
+struct Complex {
+    compounds: Vec<Compound>,
+    combinators: Vec<Combinator>,
+}
+
+impl Complex {
+    fn matches(&self, handle: &rcdom::Handle) -> bool {
+        self.matches_at(self.compounds.len() - 1, handle)
+    }
+
+    fn matches_at(&self, idx: usize, handle: &rcdom::Handle) -> bool {
+        if !self.compounds[idx].matches(handle) {
+            return false;
+        }
+        if idx == 0 {
+            return true;
+        }
+        match self.combinators[idx - 1] {
+            Combinator::Child => parent(handle).is_some_and(|p| self.matches_at(idx - 1, &p)),
+            Combinator::Descendant => ancestors(handle).any(|a| self.matches_at(idx - 1, &a)),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            chars: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn error(&self, reason: &'static str) -> SelectorError {
+        SelectorError::new(self.input, reason)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+        self.pos != start
+    }
+
+    fn ident(&mut self) -> Result<String, SelectorError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("expected an identifier"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn value(&mut self) -> Result<String, SelectorError> {
+        let quote = match self.peek() {
+            Some(q @ '"') | Some(q @ '\'') => q,
+            _ => return self.ident(),
+        };
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != quote) {
+            self.pos += 1;
+        }
+        let value = self.chars[start..self.pos].iter().collect();
+        if !self.eat(quote) {
+            return Err(self.error("unterminated string"));
+        }
+        Ok(value)
+    }
+
+    fn attr(&mut self) -> Result<Simple, SelectorError> {
+        self.skip_whitespace();
+        let name = Name::new(&self.ident()?);
+        self.skip_whitespace();
+        if self.eat(']') {
+            return Ok(Simple::Attr(name, None));
+        }
+        let op = match self.peek() {
+            Some('=') => AttrOp::Equals,
+            Some('~') => AttrOp::Includes,
+            Some('|') => AttrOp::DashMatch,
+            Some('^') => AttrOp::Prefix,
+            Some('$') => AttrOp::Suffix,
+            Some('*') => AttrOp::Substring,
+            _ => return Err(self.error("expected an attribute operator")),
+        };
+        self.pos += 1;
+        match op {
+            AttrOp::Equals => {},
+            _ => if !self.eat('=') {
+                return Err(self.error("expected an attribute operator"));
+            },
+        }
+        self.skip_whitespace();
+        let value = self.value()?;
+        self.skip_whitespace();
+        if !self.eat(']') {
+            return Err(self.error("expected `]`"));
+        }
+        Ok(Simple::Attr(name, Some((op, value))))
+    }
+
+    fn compound(&mut self) -> Result<Compound, SelectorError> {
+        let mut simples = Vec::new();
+        let universal = self.eat('*');
+        if !universal && self.peek().is_some_and(|c| c.is_alphanumeric()) {
+            simples.push(Simple::Tag(Name::new(&self.ident()?)));
+        }
+        loop {
+            match self.peek() {
+                Some('#') => {
+                    self.pos += 1;
+                    simples.push(Simple::Id(self.ident()?));
+                },
+                Some('.') => {
+                    self.pos += 1;
+                    simples.push(Simple::Class(self.ident()?));
+                },
+                Some('[') => {
+                    self.pos += 1;
+                    simples.push(self.attr()?);
+                },
+                Some(':') => return Err(self.error("pseudo-classes are not supported")),
+                _ => break,
+            }
+        }
+        if simples.is_empty() && !universal {
+            return Err(self.error("expected a selector"));
+        }
+        Ok(Compound(simples))
+    }
+
+    fn complex(&mut self) -> Result<Complex, SelectorError> {
+        let mut complex = Complex {
+            compounds: vec![self.compound()?],
+            combinators: Vec::new(),
+        };
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            let combinator = match self.peek() {
+                None | Some(',') => break,
+                Some('>') => {
+                    self.pos += 1;
+                    self.skip_whitespace();
+                    Combinator::Child
+                },
+                Some('+') | Some('~') => {
+                    return Err(self.error("sibling combinators are not supported"))
+                },
+                _ if had_whitespace => Combinator::Descendant,
+                _ => return Err(self.error("unexpected character")),
+            };
+            complex.combinators.push(combinator);
+            complex.compounds.push(self.compound()?);
+        }
+        Ok(complex)
+    }
+
+    fn list(&mut self) -> Result<Vec<Complex>, SelectorError> {
+        let mut list = Vec::new();
+        loop {
+            self.skip_whitespace();
+            list.push(self.complex()?);
+            if !self.eat(',') {
+                return Ok(list);
+            }
+        }
+    }
+}
+
+/// Selector is a parsed CSS selector that can be matched against `rcdom` elements. Only a
+/// subset of CSS is supported: type, universal, id, class and attribute selectors, joined
+/// by the descendant and child combinators, and comma separated lists of those.
+/// Pseudo-classes and sibling combinators are rejected with a `SelectorError`. Like in
+/// browsers, tag and attribute names are case insensitive on html elements and case
+/// sensitive on svg and mathml ones.
+/// # Examples
+/// ```rust
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use html5ever::parse_document;
+/// use html5ever::rcdom::RcDom;
+/// use html5ever::tendril::TendrilSink;
+/// use html5ever_stream::{Selector, NodeIter};
+///
+/// const TEST_HTML: &'static str = "<ul><li><a href='/a'>a</a></li><li><a>b</a></li></ul>";
+/// let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+/// let selector = Selector::parse("ul > li a[href]").unwrap();
+/// assert_eq!(NodeIter::new(&dom).filter(|h| selector.matches(h)).count(), 1);
+/// ```
+pub struct Selector(Vec<Complex>);
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self, SelectorError> {
+        let mut parser = Parser::new(selector);
+        let list = parser.list()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected character"));
+        }
+        Ok(Selector(list))
+    }
+
+    /// matches checks if the given node is an element matched by this selector.
+    pub fn matches(&self, handle: &rcdom::Handle) -> bool {
+        self.0.iter().any(|complex| complex.matches(handle))
+    }
+}
+
+impl FromStr for Selector {
+    type Err = SelectorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Selector::parse(s)
+    }
+}
+
+/// count_matching counts the elements in the document matched by `selector`, without
+/// collecting them.
+pub fn count_matching(dom: &rcdom::RcDom, selector: &str) -> Result<usize, SelectorError> {
+    let selector = Selector::parse(selector)?;
+    Ok(NodeIter::new(dom).filter(|h| selector.matches(h)).count())
+}
+
+/// select collects the elements in the document matched by `selector`, in document order.
+pub fn select(dom: &rcdom::RcDom, selector: &str) -> Result<Vec<rcdom::Handle>, SelectorError> {
+    let selector = Selector::parse(selector)?;
+    Ok(elements(&dom.document).filter(|h| selector.matches(h)).collect())
+}
+
+/// parse_select parses a stream and resolves to the elements matched by `selector`, in
+/// document order. Errors from the input stream are passed through. The rest of the
+/// document is dropped once the matches are collected, so the returned handles still have
+/// their children but no longer reach their parents.
+pub fn parse_select<S, C, E, D>(s: S, dom: D, selector: Selector) -> impl Future<Item=Vec<rcdom::Handle>, Error=E>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Output=rcdom::RcDom>,
+{
+    ParserFuture::new(s, dom)
+        .map(move |dom| elements(&dom.document).filter(|h| selector.matches(h)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+    use text::text_content;
+
+    const TEST_HTML: &'static str = "<html><body>\
+        <div id='main' class='content wide'><p>one</p><section><p lang='en-US'>two</p></section></div>\
+        <p class='content'>three</p>\
+        </body></html>";
+
+    #[test]
+    fn test_count_matching() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(count_matching(&dom, "p").unwrap(), 3);
+        assert_eq!(count_matching(&dom, "div p").unwrap(), 2);
+        assert_eq!(count_matching(&dom, "div > p").unwrap(), 1);
+        assert_eq!(count_matching(&dom, "#main.wide").unwrap(), 1);
+        assert_eq!(count_matching(&dom, ".content").unwrap(), 2);
+        assert_eq!(count_matching(&dom, "p[lang|=en], P.content").unwrap(), 2);
+        assert_eq!(count_matching(&dom, "body *").unwrap(), 5);
+    }
+
+    #[test]
+    fn test_foreign_names() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<svg viewBox='0 0 1 1'><foreignObject><p>x</p></foreignObject></svg><div VIEWBOX=1>");
+        assert_eq!(count_matching(&dom, "foreignObject").unwrap(), 1);
+        assert_eq!(count_matching(&dom, "foreignobject").unwrap(), 0);
+        assert_eq!(count_matching(&dom, "SVG").unwrap(), 0);
+        assert_eq!(count_matching(&dom, "foreignObject P").unwrap(), 1);
+        assert_eq!(count_matching(&dom, "[viewBox]").unwrap(), 2);
+        assert_eq!(count_matching(&dom, "[viewbox]").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        assert!(Selector::parse("").is_err());
+        assert!(Selector::parse("a:hover").is_err());
+        assert!(Selector::parse("a + b").is_err());
+        assert!(Selector::parse("a[href").is_err());
+        assert!(Selector::parse("a,").is_err());
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let chunks = vec!["<ul><li>one</li><li", ">two</li></ul><p>three</p>"];
+        let selector = Selector::parse("ul > li").unwrap();
+        let items = parse_select(stream::iter_ok::<_, ()>(chunks), RcDom::default(), selector)
+            .wait()
+            .unwrap();
+        assert_eq!(items.iter().map(text_content).collect::<Vec<_>>(), vec!["one", "two"]);
+    }
+}
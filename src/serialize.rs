@@ -0,0 +1,423 @@
+use std::io;
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use html5ever::{LocalName, QualName};
+use html5ever::serialize::{serialize, Serialize, Serializer, SerializeOpts, TraversalScope};
+use html5ever::tree_builder::TreeSink;
+
+use common::Step;
+use errors::WriteError;
+use fut::ParserFuture;
+use rcdom::{self, NodeData};
+
+/// Iterative wraps a handle so it can be fed to html5ever's serializer without going
+/// through the recursive `Serialize` impl on `rcdom::Handle`. The tree is walked with an
+/// explicit stack, so the depth of the document is bounded by the heap, not the stack.
+/// With a max_depth, the children of nodes that many levels below the handle are replaced
+/// by an elision comment. With lowercase_names, the names of html elements and of their
+/// attributes are lowercased on the way out.
+struct Iterative<'a> {
+    handle: &'a rcdom::Handle,
+    max_depth: Option<usize>,
+    lowercase_names: bool,
+}
+
+impl<'a> Iterative<'a> {
+    fn new(handle: &'a rcdom::Handle) -> Self {
+        Iterative {
+            handle,
+            max_depth: None,
+            lowercase_names: false,
+        }
+    }
+
+    /// lowercase reports if the names of an element and its attributes should be
+    /// lowercased. Only html names are case insensitive, svg and mathml keep theirs.
+    fn lowercase(&self, name: &QualName) -> bool {
+        self.lowercase_names && name.ns == ns!(html)
+    }
+}
+
+fn lowercase(name: &QualName) -> QualName {
+    QualName::new(name.prefix.clone(), name.ns.clone(),
+                  LocalName::from(&*name.local.to_ascii_lowercase()))
+}
+
+impl<'a> Serialize for Iterative<'a> {
+    fn serialize<S>(&self, serializer: &mut S, _: TraversalScope) -> io::Result<()>
+        where S: Serializer,
+    {
+        let mut stack = vec![(Step::Enter(Rc::clone(self.handle)), 0)];
+        while let Some((step, depth)) = stack.pop() {
+            let handle = match step {
+                Step::Exit(handle) => {
+                    if let NodeData::Element { ref name, .. } = handle.data {
+                        if self.lowercase(name) {
+                            serializer.end_elem(lowercase(name))?;
+                        } else {
+                            serializer.end_elem(name.clone())?;
+                        }
+                    }
+                    continue;
+                },
+                Step::Enter(handle) => handle,
+            };
+            match handle.data {
+                NodeData::Element { ref name, ref attrs, .. } => {
+                    let attrs = attrs.borrow();
+                    if self.lowercase(name) {
+                        // Namespaced attributes like xlink:href are left alone.
+                        let names: Vec<QualName> = attrs.iter()
+                            .map(|at| if at.name.ns == ns!() { lowercase(&at.name) } else { at.name.clone() })
+                            .collect();
+                        serializer.start_elem(lowercase(name),
+                            names.iter().zip(attrs.iter()).map(|(n, at)| (n, &at.value[..])))?;
+                    } else {
+                        serializer.start_elem(name.clone(),
+                            attrs.iter().map(|at| (&at.name, &at.value[..])))?;
+                    }
+                    stack.push((Step::Exit(Rc::clone(&handle)), depth));
+                },
+                NodeData::Document => {},
+                NodeData::Doctype { ref name, .. } => serializer.write_doctype(name)?,
+                NodeData::Text { ref contents } => serializer.write_text(&contents.borrow())?,
+                NodeData::Comment { ref contents } => serializer.write_comment(contents)?,
+                NodeData::ProcessingInstruction { ref target, ref contents } => {
+                    serializer.write_processing_instruction(target, contents)?
+                },
+            }
+            let children = handle.children.borrow();
+            if self.max_depth.is_some_and(|max| depth >= max) {
+                if !children.is_empty() {
+                    serializer.write_comment(" ... ")?;
+                }
+                continue;
+            }
+            for child in children.iter().rev() {
+                stack.push((Step::Enter(Rc::clone(child)), depth + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_string<T: Serialize>(node: &T) -> String {
+    let opts = SerializeOpts {
+        traversal_scope: TraversalScope::IncludeNode,
+        ..Default::default()
+    };
+    let mut buf = Vec::new();
+    serialize(&mut buf, node, opts).expect("writing to a Vec can't fail");
+    String::from_utf8(buf).expect("serializer only emits utf-8")
+}
+
+/// Pretty serializes a node with one node per line, indented by its depth. Line breaks
+/// and indentation are written as text, which is never changed by escaping.
+struct Pretty<'a> {
+    handle: &'a rcdom::Handle,
+    indent: usize,
+}
+
+impl<'a> Serialize for Pretty<'a> {
+    fn serialize<S>(&self, serializer: &mut S, _: TraversalScope) -> io::Result<()>
+        where S: Serializer,
+    {
+        let mut first = true;
+        let mut line = |serializer: &mut S, depth: usize| {
+            let mut prefix = if first { String::new() } else { "\n".to_owned() };
+            first = false;
+            prefix.extend((0..depth * self.indent).map(|_| ' '));
+            serializer.write_text(&prefix)
+        };
+        // The children of the document are the top level, at depth 0.
+        let depth = match self.handle.data {
+            NodeData::Document => 0,
+            _ => 1,
+        };
+        let mut stack = vec![(Step::Enter(Rc::clone(self.handle)), depth)];
+        while let Some((step, depth)) = stack.pop() {
+            let handle = match step {
+                Step::Exit(handle) => {
+                    if let NodeData::Element { ref name, .. } = handle.data {
+                        if !handle.children.borrow().is_empty() {
+                            line(serializer, depth - 1)?;
+                        }
+                        serializer.end_elem(name.clone())?;
+                    }
+                    continue;
+                },
+                Step::Enter(handle) => handle,
+            };
+            match handle.data {
+                NodeData::Element { ref name, ref attrs, .. } => {
+                    line(serializer, depth - 1)?;
+                    let attrs = attrs.borrow();
+                    serializer.start_elem(name.clone(), attrs.iter().map(|at| (&at.name, &at.value[..])))?;
+                    stack.push((Step::Exit(Rc::clone(&handle)), depth));
+                },
+                NodeData::Document => {},
+                NodeData::Doctype { ref name, .. } => {
+                    line(serializer, depth - 1)?;
+                    serializer.write_doctype(name)?
+                },
+                NodeData::Text { ref contents } => {
+                    let contents = contents.borrow();
+                    if !contents.trim().is_empty() {
+                        line(serializer, depth - 1)?;
+                        serializer.write_text(contents.trim())?;
+                    }
+                },
+                NodeData::Comment { ref contents } => {
+                    line(serializer, depth - 1)?;
+                    serializer.write_comment(contents)?
+                },
+                NodeData::ProcessingInstruction { ref target, ref contents } => {
+                    line(serializer, depth - 1)?;
+                    serializer.write_processing_instruction(target, contents)?
+                },
+            }
+            for child in handle.children.borrow().iter().rev() {
+                stack.push((Step::Enter(Rc::clone(child)), depth + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// StartTag serializes only the start tag of an element, none of its content.
+struct StartTag<'a>(&'a rcdom::Handle);
+
+impl<'a> Serialize for StartTag<'a> {
+    fn serialize<S>(&self, serializer: &mut S, _: TraversalScope) -> io::Result<()>
+        where S: Serializer,
+    {
+        match self.0.data {
+            NodeData::Element { ref name, ref attrs, .. } => {
+                let attrs = attrs.borrow();
+                serializer.start_elem(name.clone(), attrs.iter().map(|at| (&at.name, &at.value[..])))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+// Pieces of a document written one at a time are serialized as the children of their
+// parent, which decides if text in them is escaped.
+fn piece_opts(parent: Option<&QualName>) -> SerializeOpts {
+    SerializeOpts {
+        traversal_scope: TraversalScope::ChildrenOnly(parent.cloned()),
+        ..Default::default()
+    }
+}
+
+/// write_child serializes a node that is a child of an element named `parent`, or of the
+/// document when there is no parent.
+pub(crate) fn write_child(out: &mut Vec<u8>, parent: Option<&QualName>, handle: &rcdom::Handle) {
+    serialize(out, &Iterative::new(handle), piece_opts(parent)).expect("writing to a Vec can't fail");
+}
+
+/// write_start_tag serializes the start tag of an element with the given parent, see
+/// `write_child`. The matching end tag is up to the caller.
+pub(crate) fn write_start_tag(out: &mut Vec<u8>, parent: Option<&QualName>, handle: &rcdom::Handle) {
+    serialize(out, &StartTag(handle), piece_opts(parent)).expect("writing to a Vec can't fail");
+}
+
+/// serialize_html writes the given node and all of its descendants as HTML into `writer`.
+/// Serializing a document node writes all of its children. Unlike `html5ever::serialize`
+/// this does not recurse, so hostile, deeply nested documents can't overflow the stack.
+pub fn serialize_html<W: io::Write>(writer: W, handle: &rcdom::Handle) -> io::Result<()> {
+    let opts = SerializeOpts {
+        traversal_scope: TraversalScope::IncludeNode,
+        ..Default::default()
+    };
+    serialize(writer, &Iterative::new(handle), opts)
+}
+
+/// parse_and_write parses a stream and serializes the resulting document into `writer`,
+/// which normalizes the input the same way `canonicalize` does without building a String.
+/// Nothing is written until the whole input has been parsed. Errors from the stream and
+/// from the writer are both returned as a `WriteError`.
+pub fn parse_and_write<S, C, E, D, W>(s: S, dom: D, writer: W) -> impl Future<Item=(), Error=WriteError<E>>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Output=rcdom::RcDom>,
+          W: io::Write,
+{
+    ParserFuture::new(s, dom)
+        .map_err(WriteError::Stream)
+        .and_then(move |dom| serialize_html(writer, &dom.document).map_err(WriteError::Io))
+}
+
+/// outer_html serializes the given node, including the node itself, into a String.
+pub fn outer_html(handle: &rcdom::Handle) -> String {
+    to_string(&Iterative::new(handle))
+}
+
+/// preview_html serializes the given node like `outer_html`, but only down to `max_depth`
+/// levels below it. Where there is more content further down it is replaced by a
+/// `<!-- ... -->` comment, which keeps the output short enough for logs. Attributes of
+/// the elements that are shown are always included in full.
+pub fn preview_html(handle: &rcdom::Handle, max_depth: usize) -> String {
+    to_string(&Iterative { max_depth: Some(max_depth), ..Iterative::new(handle) })
+}
+
+/// pretty_serialize serializes the given node with every element, text, and comment on a
+/// line of its own, indented by `indent` spaces per level, which makes the structure of a
+/// document easy to read and to diff. Every element is treated as a block, inline ones like
+/// `<b>` get their own lines too, and elements without children stay on one line. Text is
+/// trimmed and whitespace only text is left out, so the output is for people to read and
+/// not a faithful copy, the text of `<pre>` or `<textarea>` changes for example.
+pub fn pretty_serialize(handle: &rcdom::Handle, indent: usize) -> String {
+    to_string(&Pretty { handle, indent })
+}
+
+/// canonicalize serializes an entire parsed document back into a String. Feeding html
+/// through the parser and back out with canonicalize normalizes it into the form
+/// html5ever would produce.
+pub fn canonicalize(dom: &rcdom::RcDom) -> String {
+    outer_html(&dom.document)
+}
+
+/// CanonicalizeOpts changes how `canonicalize_with` writes a document.
+#[derive(Debug, Clone, Default)]
+pub struct CanonicalizeOpts {
+    /// Lowercase the names of html elements and their attributes. The html parser already
+    /// lowercases them, so this only makes a difference for trees built some other way,
+    /// like by an xml parser or a custom sink. The names of svg and mathml elements are
+    /// case sensitive and are never touched.
+    pub lowercase_names: bool,
+}
+
+/// canonicalize_with serializes an entire document like `canonicalize`, with options.
+pub fn canonicalize_with(dom: &rcdom::RcDom, opts: CanonicalizeOpts) -> String {
+    to_string(&Iterative { lowercase_names: opts.lowercase_names, ..Iterative::new(&dom.document) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use futures::stream;
+    use html5ever::{parse_document, Attribute, QualName, LocalName, Namespace};
+    use html5ever::tendril::TendrilSink;
+    use html5ever::tree_builder::{TreeSink, NodeOrText, ElementFlags};
+
+    use super::*;
+    use rcdom::{RcDom, Handle};
+
+    const TEST_HTML: &'static str = "<html> <head> <title> test </title> </head> </html>";
+
+    #[test]
+    fn test_parse_and_write() {
+        let chunks = vec!["<p>a<b>b", "</p>"];
+        let mut out = Vec::new();
+        parse_and_write(stream::iter_ok::<_, ()>(chunks), RcDom::default(), &mut out).wait().unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "<html><head></head><body><p>a<b>b</b></p></body></html>");
+
+        struct Broken;
+        impl io::Write for Broken {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        match parse_and_write(stream::iter_ok::<_, ()>(vec!["<p>"]), RcDom::default(), Broken).wait() {
+            Err(WriteError::Io(ref err)) => assert_eq!(err.to_string(), "broken"),
+            _ => panic!("expected the write to fail"),
+        }
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(canonicalize(&dom),
+                   "<html><head> <title> test </title> </head> <body></body></html>");
+    }
+
+    #[test]
+    fn test_lowercase_names() {
+        let mut dom = parse_document(RcDom::default(), Default::default())
+            .one("<svg viewBox='0 0 1 1'><foreignObject></foreignObject></svg>");
+        let opts = CanonicalizeOpts { lowercase_names: true };
+        assert_eq!(canonicalize_with(&dom, opts.clone()), canonicalize(&dom));
+
+        // Build a mixed case element the html parser would never produce.
+        let name = QualName::new(None, ns!(html), LocalName::from("DIV"));
+        let attr = Attribute {
+            name: QualName::new(None, ns!(), LocalName::from("Data-X")),
+            value: "Keep".into(),
+        };
+        let div = dom.create_element(name, vec![attr], ElementFlags::default());
+        let document = Rc::clone(&dom.document);
+        dom.append(&document, NodeOrText::AppendNode(div));
+        assert!(canonicalize(&dom).ends_with("<DIV Data-X=\"Keep\"></DIV>"));
+        assert!(canonicalize_with(&dom, opts).ends_with("<div data-x=\"Keep\"></div>"));
+    }
+
+    #[test]
+    fn test_preview_html() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<div id='a' class='top'><p>text <b>bold</b></p><br></div>");
+        let div = ::select(&dom, "div").unwrap()[0].clone();
+        assert_eq!(preview_html(&div, 0), "<div id=\"a\" class=\"top\"><!-- ... --></div>");
+        assert_eq!(preview_html(&div, 1), "<div id=\"a\" class=\"top\"><p><!-- ... --></p><br></div>");
+        assert_eq!(preview_html(&div, 3), outer_html(&div));
+    }
+
+    #[test]
+    fn test_pretty_serialize() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<!DOCTYPE html><title>a &amp; b</title><p>one <b>two</b><br></p>\n<!--c-->");
+        assert_eq!(pretty_serialize(&dom.document, 2), "<!DOCTYPE html>
+<html>
+  <head>
+    <title>
+      a &amp; b
+    </title>
+  </head>
+  <body>
+    <p>
+      one
+      <b>
+        two
+      </b>
+      <br>
+    </p>
+    <!--c-->
+  </body>
+</html>");
+        let body = ::common::body(&dom).unwrap();
+        let p = Rc::clone(&body.children.borrow()[0]);
+        assert_eq!(pretty_serialize(&p, 1), "<p>\n one\n <b>\n  two\n </b>\n <br>\n</p>");
+    }
+
+    #[test]
+    fn test_deep_dom() {
+        const DEPTH: usize = 100_000;
+        // Parsing this many nested elements is quadratic in html5ever, so build the
+        // tree by hand through the TreeSink api instead.
+        let mut dom = RcDom::default();
+        let mut parent = Rc::clone(&dom.document);
+        for _ in 0..DEPTH {
+            let name = QualName::new(None, Namespace::from("http://www.w3.org/1999/xhtml"),
+                                    LocalName::from("div"));
+            let child = dom.create_element(name, vec![], ElementFlags::default());
+            dom.append(&parent, NodeOrText::AppendNode(Rc::clone(&child)));
+            parent = child;
+        }
+
+        let html = outer_html(&dom.document);
+        assert_eq!(html.len(), DEPTH * "<div></div>".len());
+        assert!(html.starts_with("<div><div>"));
+
+        // Dropping the tree is recursive as well, so take it apart from the top down.
+        let mut nodes: Vec<Handle> = vec![Rc::clone(&dom.document)];
+        while let Some(node) = nodes.pop() {
+            nodes.extend(node.children.borrow_mut().drain(..));
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::iter;
+use std::rc::Rc;
+
+use html5ever::{Attribute, ExpandedName, QualName};
+use html5ever::tendril::StrTendril;
+use html5ever::tree_builder::{
+    ElementFlags,
+    NextParserState,
+    NodeOrText,
+    QuirksMode,
+    TreeSink,
+};
+
+use common::ancestors;
+use errors::Abort;
+use rcdom;
+
+/// Hooks lets a `HookedSink` observe, and in some cases veto, the operations html5ever
+/// performs on the TreeSink it wraps. Every hook has a default that leaves the operation
+/// untouched, so implementors only need to override the ones they care about.
+pub trait Hooks<Handle> {
+    /// comment is called with the text of every comment the parser encounters. Returning
+    /// false drops the comment so it never makes it into the tree.
+    fn comment(&mut self, _text: &StrTendril) -> bool {
+        true
+    }
+
+    /// append is called before a node or text is appended to `parent`. Returning false
+    /// drops it. html5ever sometimes picks between appending to an element and inserting
+    /// before it, in that case this is called with the fallback parent.
+    fn append(&mut self, _parent: &Handle, _child: &NodeOrText<Handle>) -> bool {
+        true
+    }
+
+    /// append_before_sibling is called before a node or text is inserted right before
+    /// `sibling`, which html5ever does when foster parenting content out of tables.
+    /// Returning false drops it.
+    fn append_before_sibling(&mut self, _sibling: &Handle, _child: &NodeOrText<Handle>) -> bool {
+        true
+    }
+
+    /// creating_element is called with the name and attributes of every element right
+    /// before it is created.
+    fn creating_element(&mut self, _name: &QualName, _attrs: &[Attribute]) {}
+
+    /// element_created is called with every element right after it has been created, before
+    /// it is inserted into the tree.
+    fn element_created(&mut self, _element: &Handle) {}
+
+    /// parse_error is called with every parse error html5ever reports.
+    fn parse_error(&mut self, _msg: &str) {}
+
+    /// set_current_line is called whenever the parser moves on to a new line of input.
+    fn set_current_line(&mut self, _line: u64) {}
+}
+
+/// HookedSink wraps another `TreeSink` and forwards every operation to it, giving a set of
+/// `Hooks` a chance to inspect or filter them first.
+pub struct HookedSink<D: TreeSink, H> {
+    sink: D,
+    hooks: H,
+    dropped: Option<D::Handle>,
+}
+
+impl<D, H> HookedSink<D, H>
+    where D: TreeSink,
+          H: Hooks<D::Handle>,
+{
+    pub fn new(sink: D, hooks: H) -> Self {
+        HookedSink {
+            sink,
+            hooks,
+            dropped: None,
+        }
+    }
+
+    /// is_dropped checks if the node about to be inserted was vetoed by the hooks when it
+    /// was created. html5ever always inserts a node right after creating it, so only the
+    /// most recently dropped node needs to be remembered.
+    fn is_dropped(&mut self, child: &NodeOrText<D::Handle>) -> bool {
+        match (child, self.dropped.take()) {
+            (NodeOrText::AppendNode(node), Some(dropped)) => {
+                if self.sink.same_node(node, &dropped) {
+                    return true;
+                }
+                self.dropped = Some(dropped);
+                false
+            },
+            (_, dropped) => {
+                self.dropped = dropped;
+                false
+            },
+        }
+    }
+}
+
+impl<D, H> TreeSink for HookedSink<D, H>
+    where D: TreeSink,
+          H: Hooks<D::Handle>,
+{
+    type Handle = D::Handle;
+    type Output = D::Output;
+
+    fn finish(self) -> Self::Output {
+        self.sink.finish()
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.hooks.parse_error(&msg);
+        self.sink.parse_error(msg)
+    }
+
+    fn get_document(&mut self) -> Self::Handle {
+        self.sink.get_document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a Self::Handle) -> ExpandedName<'a> {
+        self.sink.elem_name(target)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags)
+        -> Self::Handle
+    {
+        self.hooks.creating_element(&name, &attrs);
+        let element = self.sink.create_element(name, attrs, flags);
+        self.hooks.element_created(&element);
+        element
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> Self::Handle {
+        let keep = self.hooks.comment(&text);
+        let comment = self.sink.create_comment(text);
+        if !keep {
+            self.dropped = Some(comment.clone());
+        }
+        comment
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> Self::Handle {
+        self.sink.create_pi(target, data)
+    }
+
+    fn append(&mut self, parent: &Self::Handle, child: NodeOrText<Self::Handle>) {
+        if !self.is_dropped(&child) && self.hooks.append(parent, &child) {
+            self.sink.append(parent, child)
+        }
+    }
+
+    fn append_based_on_parent_node(&mut self,
+        element: &Self::Handle,
+        prev_element: &Self::Handle,
+        child: NodeOrText<Self::Handle>)
+    {
+        if !self.is_dropped(&child) && self.hooks.append(prev_element, &child) {
+            self.sink.append_based_on_parent_node(element, prev_element, child)
+        }
+    }
+
+    fn append_doctype_to_document(&mut self,
+        name: StrTendril,
+        public_id: StrTendril,
+        system_id: StrTendril)
+    {
+        self.sink.append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn mark_script_already_started(&mut self, node: &Self::Handle) {
+        self.sink.mark_script_already_started(node)
+    }
+
+    fn pop(&mut self, node: &Self::Handle) {
+        self.sink.pop(node)
+    }
+
+    fn get_template_contents(&mut self, target: &Self::Handle) -> Self::Handle {
+        self.sink.get_template_contents(target)
+    }
+
+    fn same_node(&self, x: &Self::Handle, y: &Self::Handle) -> bool {
+        self.sink.same_node(x, y)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.sink.set_quirks_mode(mode)
+    }
+
+    fn append_before_sibling(&mut self,
+        sibling: &Self::Handle,
+        new_node: NodeOrText<Self::Handle>)
+    {
+        if !self.is_dropped(&new_node) && self.hooks.append_before_sibling(sibling, &new_node) {
+            self.sink.append_before_sibling(sibling, new_node)
+        }
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &Self::Handle, attrs: Vec<Attribute>) {
+        self.sink.add_attrs_if_missing(target, attrs)
+    }
+
+    fn associate_with_form(&mut self,
+        target: &Self::Handle,
+        form: &Self::Handle,
+        nodes: (&Self::Handle, Option<&Self::Handle>))
+    {
+        self.sink.associate_with_form(target, form, nodes)
+    }
+
+    fn remove_from_parent(&mut self, target: &Self::Handle) {
+        self.sink.remove_from_parent(target)
+    }
+
+    fn reparent_children(&mut self, node: &Self::Handle, new_parent: &Self::Handle) {
+        self.sink.reparent_children(node, new_parent)
+    }
+
+    fn is_mathml_annotation_xml_integration_point(&self, handle: &Self::Handle) -> bool {
+        self.sink.is_mathml_annotation_xml_integration_point(handle)
+    }
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.hooks.set_current_line(line_number);
+        self.sink.set_current_line(line_number)
+    }
+
+    fn complete_script(&mut self, node: &Self::Handle) -> NextParserState {
+        self.sink.complete_script(node)
+    }
+}
+
+/// StripComments drops every comment from the tree being built, including IE style
+/// conditional comments, which html5ever parses as ordinary comments.
+pub struct StripComments;
+
+impl<Handle> Hooks<Handle> for StripComments {
+    fn comment(&mut self, _text: &StrTendril) -> bool {
+        false
+    }
+}
+
+/// StripText drops every piece of text from the tree being built, leaving only elements,
+/// comments and the doctype. That includes whitespace between elements and the contents
+/// of `<script>`, `<style>` and `<textarea>` elements.
+pub struct StripText;
+
+impl<Handle> Hooks<Handle> for StripText {
+    fn append(&mut self, _parent: &Handle, child: &NodeOrText<Handle>) -> bool {
+        !matches!(*child, NodeOrText::AppendText(_))
+    }
+
+    fn append_before_sibling(&mut self, _sibling: &Handle, child: &NodeOrText<Handle>) -> bool {
+        !matches!(*child, NodeOrText::AppendText(_))
+    }
+}
+
+/// Tripwire is shared between hooks that enforce a limit and the stream feeding the
+/// parser. Hooks trip it when a limit is exceeded and the stream checks it before handing
+/// the parser more input, which is how a limit turns into a future error.
+#[derive(Clone, Default)]
+pub(crate) struct Tripwire(Rc<RefCell<Option<Abort>>>);
+
+impl Tripwire {
+    /// trip records why the parse should be aborted, only the first reason is kept.
+    pub(crate) fn trip(&self, abort: Abort) {
+        let mut reason = self.0.borrow_mut();
+        if reason.is_none() {
+            *reason = Some(abort);
+        }
+    }
+
+    pub(crate) fn tripped(&self) -> Option<Abort> {
+        self.0.borrow().clone()
+    }
+}
+
+/// DepthLimit trips when a node is inserted deeper than `max` levels below the document.
+/// The depth is measured by walking up the parent links of the insertion point, and the
+/// walk is capped at `max` steps so the cost per insertion stays bounded.
+pub struct DepthLimit {
+    max: usize,
+    tripwire: Tripwire,
+}
+
+impl DepthLimit {
+    pub(crate) fn new(max: usize, tripwire: Tripwire) -> Self {
+        DepthLimit {
+            max,
+            tripwire,
+        }
+    }
+
+    /// check counts the chain of nodes from the new node's parent up to the document, which
+    /// is the depth the new node ends up at.
+    fn check<I: Iterator>(&self, chain: I) {
+        if chain.take(self.max + 1).count() > self.max {
+            self.tripwire.trip(Abort::MaxDepthExceeded(self.max));
+        }
+    }
+}
+
+impl Hooks<rcdom::Handle> for DepthLimit {
+    fn append(&mut self, parent: &rcdom::Handle, _child: &NodeOrText<rcdom::Handle>) -> bool {
+        self.check(iter::once(Rc::clone(parent)).chain(ancestors(parent)));
+        true
+    }
+
+    fn append_before_sibling(&mut self,
+        sibling: &rcdom::Handle,
+        _child: &NodeOrText<rcdom::Handle>) -> bool
+    {
+        self.check(ancestors(sibling));
+        true
+    }
+}
+
+/// MaxAttrs trips when an element is created with more than `max` attributes.
+pub struct MaxAttrs {
+    max: usize,
+    tripwire: Tripwire,
+}
+
+impl MaxAttrs {
+    pub(crate) fn new(max: usize, tripwire: Tripwire) -> Self {
+        MaxAttrs {
+            max,
+            tripwire,
+        }
+    }
+}
+
+impl<Handle> Hooks<Handle> for MaxAttrs {
+    fn creating_element(&mut self, name: &QualName, attrs: &[Attribute]) {
+        if attrs.len() > self.max {
+            self.tripwire.trip(Abort::TooManyAttributes(name.local.clone(), self.max));
+        }
+    }
+}
@@ -0,0 +1,418 @@
+use html5ever::tendril::StrTendril;
+
+use common::{EventIter, DomEvent};
+use extract::attr;
+use rcdom::{self, NodeData};
+
+/// text_content concatenates the contents of every text node below the given node, in
+/// document order, exactly as they appear in the tree.
+pub fn text_content(handle: &rcdom::Handle) -> String {
+    let mut text = String::new();
+    for event in EventIter::from_handle(handle) {
+        if let DomEvent::Text(contents) = event {
+            text.push_str(&contents);
+        }
+    }
+    text
+}
+
+enum Mode {
+    Normal,
+    Verbatim,
+    Hidden,
+}
+
+fn mode(handle: &rcdom::Handle) -> Mode {
+    match handle.data {
+        NodeData::Element { ref name, .. } => match name.local {
+            local_name!("pre") | local_name!("textarea") => Mode::Verbatim,
+            local_name!("script") | local_name!("style") => Mode::Hidden,
+            _ => Mode::Normal,
+        },
+        _ => Mode::Normal,
+    }
+}
+
+/// is_block reports if an element starts on a new line when rendered, or forces a line
+/// break like `<br>`. Table cells are left out so a row reads as a single line.
+fn is_block(handle: &rcdom::Handle) -> bool {
+    match handle.data {
+        NodeData::Element { ref name, .. } => name.ns == ns!(html) && matches!(name.local,
+            local_name!("address") | local_name!("article") | local_name!("aside") |
+            local_name!("blockquote") | local_name!("body") | local_name!("br") |
+            local_name!("caption") | local_name!("dd") | local_name!("details") |
+            local_name!("dialog") | local_name!("div") | local_name!("dl") | local_name!("dt") |
+            local_name!("fieldset") | local_name!("figcaption") | local_name!("figure") |
+            local_name!("footer") | local_name!("form") | local_name!("h1") | local_name!("h2") |
+            local_name!("h3") | local_name!("h4") | local_name!("h5") | local_name!("h6") |
+            local_name!("header") | local_name!("hr") | local_name!("html") | local_name!("legend") |
+            local_name!("li") | local_name!("main") | local_name!("nav") | local_name!("ol") |
+            local_name!("p") | local_name!("pre") | local_name!("section") | local_name!("summary") |
+            local_name!("table") | local_name!("textarea") | local_name!("title") |
+            local_name!("tr") | local_name!("ul")),
+        _ => false,
+    }
+}
+
+/// Chunk is a piece of visible text, or the edge of a block element.
+enum Chunk {
+    /// The contents of a text node, along with a flag saying if it is inside an element
+    /// that keeps its whitespace verbatim.
+    Text(StrTendril, bool),
+    /// The start or end of a block element.
+    Block,
+}
+
+/// Visible replays the text of a document the way it is rendered, skipping the contents of
+/// hidden elements.
+struct Visible {
+    events: EventIter,
+    // Stack of the modes introduced by the currently open elements, Normal is implied.
+    modes: Vec<Mode>,
+}
+
+impl Visible {
+    fn new(dom: &rcdom::RcDom) -> Self {
+        Visible {
+            events: EventIter::new(dom),
+            modes: Vec::new(),
+        }
+    }
+
+    fn hidden(&self) -> bool {
+        matches!(self.modes.last(), Some(&Mode::Hidden))
+    }
+}
+
+impl Iterator for Visible {
+    type Item = Chunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.events.next()? {
+                DomEvent::Open(handle) => {
+                    match mode(&handle) {
+                        Mode::Normal => {},
+                        m => self.modes.push(m),
+                    }
+                    if is_block(&handle) && !self.hidden() {
+                        return Some(Chunk::Block);
+                    }
+                },
+                DomEvent::Close(handle) => {
+                    match mode(&handle) {
+                        Mode::Normal => {},
+                        _ => { self.modes.pop(); },
+                    }
+                    if is_block(&handle) && !self.hidden() {
+                        return Some(Chunk::Block);
+                    }
+                },
+                DomEvent::Text(contents) => match self.modes.last() {
+                    Some(&Mode::Hidden) => {},
+                    Some(&Mode::Verbatim) => return Some(Chunk::Text(contents, true)),
+                    _ => return Some(Chunk::Text(contents, false)),
+                },
+            }
+        }
+    }
+}
+
+/// normalized_text extracts the text of a document roughly the way a browser would render
+/// it. Runs of whitespace collapse into a single space and leading and trailing whitespace
+/// is dropped, except inside `<pre>` and `<textarea>` elements whose contents are kept
+/// verbatim. The edges of block elements like `<p>` or `<li>` count as whitespace too, so
+/// the text of neighbouring blocks is separated by a space. The contents of `<script>` and
+/// `<style>` elements are skipped entirely.
+pub fn normalized_text(dom: &rcdom::RcDom) -> String {
+    let mut text = String::new();
+    let mut pending_space = false;
+    for chunk in Visible::new(dom) {
+        let (contents, verbatim) = match chunk {
+            Chunk::Text(contents, verbatim) => (contents, verbatim),
+            Chunk::Block => {
+                pending_space = true;
+                continue;
+            },
+        };
+        if verbatim {
+            if pending_space && !text.is_empty() {
+                text.push(' ');
+            }
+            pending_space = false;
+            text.push_str(&contents);
+            continue;
+        }
+        if contents.starts_with(char::is_whitespace) {
+            pending_space = true;
+        }
+        for (i, word) in contents.split_whitespace().enumerate() {
+            if (i > 0 || pending_space) && !text.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(word);
+            pending_space = false;
+        }
+        if contents.ends_with(char::is_whitespace) {
+            pending_space = true;
+        }
+    }
+    text
+}
+
+/// word_count counts the words in the visible text of a document, the same text
+/// `normalized_text` extracts. A word is a maximal run of non-whitespace characters, so a
+/// word split across inline elements like `bold<b>er</b>` counts once. The edges of block
+/// elements end a word, so `<li>apple</li><li>pear</li>` is two words.
+pub fn word_count(dom: &rcdom::RcDom) -> usize {
+    let mut count = 0;
+    let mut in_word = false;
+    for chunk in Visible::new(dom) {
+        let contents = match chunk {
+            Chunk::Text(contents, _) => contents,
+            Chunk::Block => {
+                in_word = false;
+                continue;
+            },
+        };
+        for c in contents.chars() {
+            if c.is_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                count += 1;
+                in_word = true;
+            }
+        }
+    }
+    count
+}
+
+/// text_to_markup_ratio returns the length in bytes of the text `normalized_text` extracts
+/// divided by `original_len`, the length in bytes of the html it was parsed from. Pages
+/// that are mostly prose, like articles, come out high while navigation heavy or script
+/// heavy pages come out low. The dom doesn't know how long its source was, count it while
+/// parsing, for example with `ParserFuture::inspect_chunks`. An `original_len` of 0 gives
+/// 0.0. Whitespace collapsing and entity decoding can make the text shorter or longer than
+/// it was in the source, so the ratio is a heuristic and can slightly exceed 1.0.
+pub fn text_to_markup_ratio(dom: &rcdom::RcDom, original_len: usize) -> f64 {
+    if original_len == 0 {
+        return 0.0;
+    }
+    normalized_text(dom).len() as f64 / original_len as f64
+}
+
+/// Gap is the whitespace owed between the text written so far and whatever comes next.
+#[derive(PartialEq, PartialOrd)]
+enum Gap {
+    None,
+    Space,
+    Line,
+}
+
+/// block_text extracts the text of a document laid out roughly the way a browser shows
+/// it. Block elements like `<p>`, `<div>` and `<li>` start on a new line and `<br>` breaks
+/// the line, so each block ends up on its own line with no blank lines in between. Within
+/// a block whitespace collapses into single spaces like in `normalized_text`, and the same
+/// elements are kept verbatim or skipped.
+pub fn block_text(dom: &rcdom::RcDom) -> String {
+    let mut text = String::new();
+    let mut gap = Gap::None;
+    for chunk in Visible::new(dom) {
+        let (contents, verbatim) = match chunk {
+            Chunk::Block => {
+                gap = Gap::Line;
+                continue;
+            },
+            Chunk::Text(contents, verbatim) => (contents, verbatim),
+        };
+        if !verbatim && contents.starts_with(char::is_whitespace) && gap < Gap::Space {
+            gap = Gap::Space;
+        }
+        let words: Vec<&str> = if verbatim {
+            vec![&contents]
+        } else {
+            contents.split_whitespace().collect()
+        };
+        for (i, word) in words.into_iter().enumerate() {
+            if word.is_empty() {
+                continue;
+            }
+            if i > 0 && gap < Gap::Space {
+                gap = Gap::Space;
+            }
+            if !text.is_empty() {
+                match gap {
+                    Gap::Line => text.push('\n'),
+                    Gap::Space => text.push(' '),
+                    Gap::None => {},
+                }
+            }
+            text.push_str(word);
+            gap = Gap::None;
+        }
+        if !verbatim && contents.ends_with(char::is_whitespace) && gap < Gap::Space {
+            gap = Gap::Space;
+        }
+    }
+    text
+}
+
+/// StyledSpan is a run of text along with the inline styles in effect for it, see
+/// `to_spans`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StyledSpan {
+    /// The contents of the text node.
+    pub text: String,
+    /// Inside `<b>` or `<strong>`.
+    pub bold: bool,
+    /// Inside `<i>`, `<em>`, `<cite>`, `<dfn>` or `<var>`.
+    pub italic: bool,
+    /// Inside `<u>` or `<ins>`.
+    pub underline: bool,
+    /// Inside `<s>`, `<strike>` or `<del>`.
+    pub strikethrough: bool,
+    /// Inside `<code>`, `<kbd>`, `<samp>`, `<tt>` or `<pre>`, usually shown in a monospace
+    /// font.
+    pub code: bool,
+    /// The href of the innermost `<a>` with one.
+    pub link: Option<String>,
+}
+
+/// style returns the style of the text inside `handle`, given the style of the text
+/// around it, or None when the element doesn't change it.
+fn style(handle: &rcdom::Handle, outer: &StyledSpan) -> Option<StyledSpan> {
+    let local = match handle.data {
+        NodeData::Element { ref name, .. } if name.ns == ns!(html) => name.local.clone(),
+        _ => return None,
+    };
+    let mut style = outer.clone();
+    match local {
+        local_name!("b") | local_name!("strong") => style.bold = true,
+        local_name!("i") | local_name!("em") | local_name!("cite") | local_name!("dfn") |
+        local_name!("var") => style.italic = true,
+        local_name!("u") | local_name!("ins") => style.underline = true,
+        local_name!("s") | local_name!("strike") | local_name!("del") => style.strikethrough = true,
+        local_name!("code") | local_name!("kbd") | local_name!("samp") | local_name!("tt") |
+        local_name!("pre") => style.code = true,
+        local_name!("a") => style.link = Some(attr(handle, "href")?),
+        _ => return None,
+    }
+    Some(style)
+}
+
+/// to_spans flattens the visible text of a document into spans, one per text node in
+/// document order, each carrying the inline styles of the elements around it. That is
+/// enough for a terminal or immediate mode ui to render the text, bold for `<b>` and so
+/// on, without walking the tree itself. Text is kept exactly as it is in the tree, and the
+/// contents of `<script>` and `<style>` elements are skipped like in `normalized_text`.
+/// Spans carry no layout, block elements don't show up in them at all.
+pub fn to_spans(dom: &rcdom::RcDom) -> Vec<StyledSpan> {
+    let mut spans = Vec::new();
+    // The styles introduced by the currently open elements, innermost last.
+    let mut styles: Vec<StyledSpan> = Vec::new();
+    let mut open: Vec<bool> = Vec::new();
+    let mut hidden = 0;
+    let plain = StyledSpan::default();
+    for event in EventIter::new(dom) {
+        match event {
+            DomEvent::Open(handle) => {
+                if let Mode::Hidden = mode(&handle) {
+                    hidden += 1;
+                }
+                let inner = style(&handle, styles.last().unwrap_or(&plain));
+                open.push(inner.is_some());
+                styles.extend(inner);
+            },
+            DomEvent::Close(handle) => {
+                if let Mode::Hidden = mode(&handle) {
+                    hidden -= 1;
+                }
+                if open.pop() == Some(true) {
+                    styles.pop();
+                }
+            },
+            DomEvent::Text(contents) => {
+                if hidden == 0 {
+                    spans.push(StyledSpan {
+                        text: contents.to_string(),
+                        ..styles.last().unwrap_or(&plain).clone()
+                    });
+                }
+            },
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use html5ever::parse_document;
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    const TEST_HTML: &'static str = "<html><head><title>  a\n title </title>\
+        <style>p { color: red }</style></head>\
+        <body>  some   <b>bold</b>text\t\n<pre>  keep\n   this </pre> done  </body></html>";
+
+    #[test]
+    fn test_text_content() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(text_content(&dom.document),
+                   "  a\n title p { color: red }  some   boldtext\t\n  keep\n   this  done  ");
+    }
+
+    #[test]
+    fn test_normalized_text() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(normalized_text(&dom), "a title some boldtext   keep\n   this  done");
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<ul><li>apple</li><li>pear</li></ul><p>one</p><p>two</p>");
+        assert_eq!(normalized_text(&dom), "apple pear one two");
+    }
+
+    #[test]
+    fn test_block_text() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(block_text(&dom), "a title\nsome boldtext\n  keep\n   this \ndone");
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<div><p>one <i>two</i></p><p>three<br>four</p></div><ul><li>a<li>b</ul>");
+        assert_eq!(block_text(&dom), "one two\nthree\nfour\na\nb");
+    }
+
+    #[test]
+    fn test_word_count() {
+        let dom = parse_document(RcDom::default(), Default::default()).one(TEST_HTML);
+        assert_eq!(word_count(&dom), 7);
+        let empty = parse_document(RcDom::default(), Default::default()).one("<script>a b</script>");
+        assert_eq!(word_count(&empty), 0);
+        let blocks = parse_document(RcDom::default(), Default::default())
+            .one("<ul><li>apple</li><li>pear</li></ul><p>one</p><p>two</p>");
+        assert_eq!(word_count(&blocks), 4);
+    }
+
+    #[test]
+    fn test_text_to_markup_ratio() {
+        let html = "<p>four</p><script>skipped</script>";
+        let dom = parse_document(RcDom::default(), Default::default()).one(html);
+        assert_eq!(text_to_markup_ratio(&dom, html.len()), 4.0 / html.len() as f64);
+        assert_eq!(text_to_markup_ratio(&dom, 0), 0.0);
+    }
+
+    #[test]
+    fn test_to_spans() {
+        let dom = parse_document(RcDom::default(), Default::default())
+            .one("<p>a <b>b <i>c</i></b><a href='/x'><code>d</code></a><a>e</a>\
+                  <script>f</script><del>g</del></p>");
+        let span = |text: &str| StyledSpan { text: text.to_owned(), ..Default::default() };
+        assert_eq!(to_spans(&dom), vec![
+            span("a "),
+            StyledSpan { bold: true, ..span("b ") },
+            StyledSpan { bold: true, italic: true, ..span("c") },
+            StyledSpan { code: true, link: Some("/x".to_owned()), ..span("d") },
+            span("e"),
+            StyledSpan { strikethrough: true, ..span("g") },
+        ]);
+    }
+}
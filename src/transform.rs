@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+use std::mem;
+use std::rc::Rc;
+
+use bytes::Bytes;
+use futures::{Stream, Poll, Async};
+use html5ever::QualName;
+use html5ever::tree_builder::TreeSink;
+
+use closed::ClosedElementStream;
+use common::{elements, is_html, parent};
+use mutate::detach;
+use rcdom::{self, Node, NodeData};
+use serialize::{write_child, write_start_tag};
+
+/// TransformStream is a streaming html rewriter. It parses a stream of bytes, calls
+/// `rewrite` on every element once the element has been closed, children before their
+/// parents, and yields the rewritten document as html while the input is still coming
+/// in. `rewrite` can change the element and anything inside it, like its attributes or
+/// children, or detach it to drop it from the output.
+///
+/// Output is written as soon as the parser can no longer change it, and every node that
+/// has been written is dropped from the dom, so memory stays bounded by what is still
+/// open rather than by the size of the document. The content of an element that is still
+/// open is written as it completes, behind the element's start tag. By the time `rewrite`
+/// sees such an element, changes to its own name and attributes, or to the text and
+/// comments directly inside it that are already out, are too late to show up. The same
+/// goes for attributes a later duplicate `<html>` or `<body>` tag adds. Child elements are
+/// passed to `rewrite` themselves before they are written, so rewriting those in their
+/// own call always works. Elements that arrive in one chunk are seen whole.
+///
+/// The parser can still move content around inside tables, templates and misnested
+/// formatting elements like `<b>`, so those are only written once they are closed as a
+/// whole. A page wrapped in one of them, or with a formatting element left open, is only
+/// written in full when the stream ends.
+/// # Examples
+/// ```rust
+/// extern crate futures;
+/// extern crate html5ever;
+/// extern crate html5ever_stream;
+///
+/// use futures::{stream, Future, Stream};
+/// use html5ever::{Attribute, QualName};
+/// use html5ever::rcdom::{RcDom, NodeData};
+/// use html5ever_stream::TransformStream;
+///
+/// let chunks = vec!["<p><a href='/a'>a</a></p><p>", "<a href='/b'>b</a></p>"];
+/// let html = TransformStream::new(stream::iter_ok::<_, ()>(chunks), RcDom::default(), |handle| {
+///     if let NodeData::Element { ref name, ref attrs, .. } = handle.data {
+///         if &*name.local == "a" {
+///             attrs.borrow_mut().push(Attribute {
+///                 name: QualName::new(None, "".into(), "rel".into()),
+///                 value: "nofollow".into(),
+///             });
+///         }
+///     }
+/// }).concat2().wait().unwrap();
+/// assert_eq!(&html[..], &b"<html><head></head><body>\
+///     <p><a href=\"/a\" rel=\"nofollow\">a</a></p>\
+///     <p><a href=\"/b\" rel=\"nofollow\">b</a></p></body></html>"[..]);
+/// ```
+#[must_use = "streams do nothing unless polled"]
+pub struct TransformStream<S, D, F>
+    where D: TreeSink<Handle=rcdom::Handle>,
+{
+    closed: ClosedElementStream<S, D>,
+    rewrite: F,
+    document: rcdom::Handle,
+    // Elements `rewrite` has been called on that haven't been written yet.
+    rewritten: HashSet<*const Node>,
+    // Open elements whose start tag has been written, outermost first.
+    written: Vec<rcdom::Handle>,
+    out: Vec<u8>,
+    finished: bool,
+}
+
+impl<S, C, E, D, F> TransformStream<S, D, F>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+          F: FnMut(&rcdom::Handle),
+{
+    pub fn new(s: S, dom: D, rewrite: F) -> Self {
+        let mut closed = ClosedElementStream::new(s, dom);
+        let document = closed.document().expect("a new stream has a parser");
+        TransformStream {
+            closed,
+            rewrite,
+            document,
+            rewritten: HashSet::new(),
+            written: Vec::new(),
+            out: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn is_rewritten(&self, handle: &rcdom::Handle) -> bool {
+        self.rewritten.contains(&(&**handle as *const Node))
+    }
+
+    /// done checks if nothing more can be added to the children of `handle`.
+    fn done(&self, handle: &rcdom::Handle) -> bool {
+        if Rc::ptr_eq(handle, &self.document) {
+            return self.finished;
+        }
+        // The tree builder holds on to the head until the end. Elements like `<title>`
+        // that show up after `</head>` still go into it, until there is a body or a
+        // frameset to put them in instead.
+        if is_html(handle, &local_name!("head")) {
+            return self.is_rewritten(handle) || has_body_after(handle);
+        }
+        self.is_rewritten(handle)
+    }
+
+    /// flush writes out everything at the front of the document that can't change
+    /// anymore, and drops it from the dom.
+    fn flush(&mut self) {
+        loop {
+            let parent = self.written.last().cloned().unwrap_or_else(|| Rc::clone(&self.document));
+            let parent_name = element_name(&parent);
+            let first = parent.children.borrow().first().cloned();
+            let child = match first {
+                Some(child) => child,
+                None => {
+                    if self.written.is_empty() || !self.done(&parent) {
+                        return;
+                    }
+                    if let Some(ref name) = parent_name {
+                        self.out.extend_from_slice(b"</");
+                        self.out.extend_from_slice(name.local.as_bytes());
+                        self.out.push(b'>');
+                    }
+                    self.written.pop();
+                    self.forget(&parent);
+                    detach(&parent);
+                    continue;
+                },
+            };
+            let is_last = parent.children.borrow().len() == 1;
+            if let NodeData::Element { .. } = child.data {
+                if self.is_rewritten(&child) {
+                    write_child(&mut self.out, parent_name.as_ref(), &child);
+                    for element in elements(&child) {
+                        self.forget(&element);
+                    }
+                    detach(&child);
+                } else if can_enter(&child) {
+                    write_start_tag(&mut self.out, parent_name.as_ref(), &child);
+                    self.written.push(child);
+                } else {
+                    return;
+                }
+            } else if !is_last || self.done(&parent) {
+                // Text can still be added to the last child if it is a text node, anything
+                // followed by another node is complete.
+                write_child(&mut self.out, parent_name.as_ref(), &child);
+                detach(&child);
+            } else {
+                return;
+            }
+        }
+    }
+
+    fn forget(&mut self, handle: &rcdom::Handle) {
+        self.rewritten.remove(&(&**handle as *const Node));
+    }
+}
+
+fn element_name(handle: &rcdom::Handle) -> Option<QualName> {
+    match handle.data {
+        NodeData::Element { ref name, .. } => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn has_body_after(handle: &rcdom::Handle) -> bool {
+    let is_body = |child: &rcdom::Handle| {
+        is_html(child, &local_name!("body")) || is_html(child, &local_name!("frameset"))
+    };
+    match parent(handle) {
+        Some(parent) => parent.children.borrow().iter()
+            .skip_while(|child| !Rc::ptr_eq(child, handle))
+            .any(is_body),
+        None => false,
+    }
+}
+
+/// can_enter checks if the content of an open element can be written before the element
+/// is closed. The tree builder moves nodes around inside tables, templates and formatting
+/// elements after they have been inserted.
+fn can_enter(handle: &rcdom::Handle) -> bool {
+    match handle.data {
+        NodeData::Element { ref name, .. } if name.ns == ns!(html) => !matches!(name.local,
+            local_name!("table") | local_name!("template") |
+            local_name!("a") | local_name!("b") | local_name!("big") | local_name!("code") |
+            local_name!("em") | local_name!("font") | local_name!("i") | local_name!("nobr") |
+            local_name!("s") | local_name!("small") | local_name!("strike") |
+            local_name!("strong") | local_name!("tt") | local_name!("u")),
+        NodeData::Element { .. } => true,
+        _ => false,
+    }
+}
+
+impl<S, C, E, D, F> Stream for TransformStream<S, D, F>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink<Handle=rcdom::Handle>,
+          F: FnMut(&rcdom::Handle),
+{
+    type Item = Bytes;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if !self.out.is_empty() {
+                let out = mem::take(&mut self.out);
+                return Ok(Async::Ready(Some(Bytes::from(out))));
+            }
+            if self.finished {
+                return Ok(Async::Ready(None));
+            }
+            match self.closed.poll()? {
+                Async::Ready(Some(handle)) => {
+                    (self.rewrite)(&handle);
+                    self.rewritten.insert(&*handle as *const Node);
+                    // Elements closed by the same chunk come out one after the other, wait
+                    // until all of them have been rewritten.
+                    if self.closed.batch_done() {
+                        self.flush();
+                    }
+                },
+                Async::Ready(None) => {
+                    self.finished = true;
+                    self.flush();
+                },
+                Async::NotReady => {
+                    self.flush();
+                    if self.out.is_empty() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future, Stream};
+    use html5ever::{parse_document, Attribute};
+    use html5ever::tendril::TendrilSink;
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn upper_text(handle: &rcdom::Handle) {
+        for child in handle.children.borrow().iter() {
+            if let NodeData::Text { ref contents } = child.data {
+                let upper = contents.borrow().to_uppercase();
+                *contents.borrow_mut() = upper.into();
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_before_stream_ends() {
+        let chunks = vec![
+            Ok("<!DOCTYPE html><html><body><p>one</p><p>t"),
+            Ok("wo</p><div>three"),
+            Err(()),
+        ];
+        let transformed = TransformStream::new(stream::iter_result(chunks), RcDom::default(),
+                                               upper_text);
+        let (first, rest) = transformed.into_future().wait().map_err(|_| ()).unwrap();
+        assert_eq!(&first.unwrap()[..], &b"<!DOCTYPE html><html><head></head><body><p>ONE</p><p>"[..]);
+        let (second, rest) = rest.into_future().wait().map_err(|_| ()).unwrap();
+        assert_eq!(&second.unwrap()[..], &b"TWO</p><div>"[..]);
+        assert!(rest.into_future().wait().is_err());
+    }
+
+    #[test]
+    fn test_transform_head_after_end_tag() {
+        let chunks = vec!["<head><meta charset=utf-8></head>\n", "<title>t</title><body><p>x</p>"];
+        let transformed = TransformStream::new(stream::iter_ok::<_, ()>(chunks.clone()),
+                                               RcDom::default(), |_: &rcdom::Handle| {})
+            .concat2()
+            .wait()
+            .unwrap();
+        let dom = parse_document(RcDom::default(), Default::default()).one(chunks.concat());
+        assert_eq!(String::from_utf8(transformed.to_vec()).unwrap(), ::canonicalize(&dom));
+    }
+
+    // Only touches elements that are never written before they are closed.
+    fn mark(handle: &rcdom::Handle) {
+        if let NodeData::Element { ref name, ref attrs, .. } = handle.data {
+            if matches!(&*name.local, "b" | "td" | "path") {
+                attrs.borrow_mut().push(Attribute {
+                    name: QualName::new(None, ns!(), local_name!("class")),
+                    value: "marked".into(),
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_matches_dom() {
+        let html = "<title>a&amp;b</title><p>x<b>y<table><tr><td>z</table>\
+            <script>if (a < b) {}</script><template><i></i></template>\
+            <svg><path/></svg><ul><li>1<li>2</ul>";
+        let chunks: Vec<String> = html.chars().map(|c| c.to_string()).collect();
+        let transformed = TransformStream::new(stream::iter_ok::<_, ()>(chunks), RcDom::default(),
+                                               mark)
+            .concat2()
+            .wait()
+            .unwrap();
+        let dom = parse_document(RcDom::default(), Default::default()).one(html);
+        for element in elements(&dom.document) {
+            mark(&element);
+        }
+        assert_eq!(String::from_utf8(transformed.to_vec()).unwrap(), ::canonicalize(&dom));
+    }
+}
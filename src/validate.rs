@@ -0,0 +1,351 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use futures::{Future, Stream, Poll, Async};
+use html5ever::{parse_document, Parser, QualName, ExpandedName, Attribute};
+use html5ever::tendril::{StrTendril, TendrilSink};
+use html5ever::tendril::stream::Utf8LossyDecoder;
+use html5ever::tree_builder::{TreeBuilder, TreeSink, Tracer, NodeOrText, ElementFlags, QuirksMode};
+
+use errors::{Abort, Error};
+use fut::{Budget, ParserFuture};
+
+/// NameSink is a `TreeSink` that only remembers the name of every element it creates,
+/// nodes are numbered in the order they were created and the tree itself is thrown away.
+#[derive(Default)]
+struct NameSink {
+    // Indexed by handle, None for the document, comments and processing instructions.
+    names: Vec<Option<QualName>>,
+}
+
+impl NameSink {
+    fn node(&mut self, name: Option<QualName>) -> usize {
+        // The document is created up front as node 0.
+        if self.names.is_empty() {
+            self.names.push(None);
+        }
+        self.names.push(name);
+        self.names.len() - 1
+    }
+}
+
+impl TreeSink for NameSink {
+    type Handle = usize;
+    type Output = ();
+
+    fn finish(self) {}
+
+    fn parse_error(&mut self, _msg: Cow<'static, str>) {}
+
+    fn get_document(&mut self) -> usize {
+        0
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a usize) -> ExpandedName<'a> {
+        self.names[*target].as_ref().expect("not an element").expanded()
+    }
+
+    fn create_element(&mut self, name: QualName, _attrs: Vec<Attribute>, _flags: ElementFlags) -> usize {
+        self.node(Some(name))
+    }
+
+    fn create_comment(&mut self, _text: StrTendril) -> usize {
+        self.node(None)
+    }
+
+    fn create_pi(&mut self, _target: StrTendril, _data: StrTendril) -> usize {
+        self.node(None)
+    }
+
+    fn append(&mut self, _parent: &usize, _child: NodeOrText<usize>) {}
+
+    fn append_based_on_parent_node(&mut self, _element: &usize, _prev_element: &usize,
+                                   _child: NodeOrText<usize>) {}
+
+    fn append_doctype_to_document(&mut self, _name: StrTendril, _public_id: StrTendril,
+                                  _system_id: StrTendril) {}
+
+    fn get_template_contents(&mut self, _target: &usize) -> usize {
+        self.node(None)
+    }
+
+    fn same_node(&self, x: &usize, y: &usize) -> bool {
+        x == y
+    }
+
+    fn set_quirks_mode(&mut self, _mode: QuirksMode) {}
+
+    fn append_before_sibling(&mut self, _sibling: &usize, _new_node: NodeOrText<usize>) {}
+
+    fn add_attrs_if_missing(&mut self, _target: &usize, _attrs: Vec<Attribute>) {}
+
+    fn remove_from_parent(&mut self, _target: &usize) {}
+
+    fn reparent_children(&mut self, _node: &usize, _new_parent: &usize) {}
+}
+
+/// optional_end reports if the spec allows leaving out the end tag of an element.
+fn optional_end(name: &QualName) -> bool {
+    if name.ns != ns!(html) {
+        return false;
+    }
+    matches!(name.local,
+        local_name!("html") | local_name!("head") | local_name!("body") |
+        local_name!("li") | local_name!("dt") | local_name!("dd") | local_name!("p") |
+        local_name!("rt") | local_name!("rp") | local_name!("optgroup") | local_name!("option") |
+        local_name!("colgroup") | local_name!("caption") | local_name!("thead") |
+        local_name!("tbody") | local_name!("tfoot") | local_name!("tr") | local_name!("td") |
+        local_name!("th"))
+}
+
+/// Handles collects the nodes the tree builder of any sink still holds on to, in the
+/// order it reports them.
+struct Handles<H>(RefCell<Vec<H>>);
+
+impl<H: Clone> Tracer for Handles<H> {
+    type Handle = H;
+
+    fn trace_handle(&self, node: &H) {
+        self.0.borrow_mut().push(node.clone());
+    }
+}
+
+fn is_formatting(name: &QualName) -> bool {
+    name.ns == ns!(html) && matches!(name.local,
+        local_name!("a") | local_name!("b") | local_name!("big") | local_name!("code") |
+        local_name!("em") | local_name!("font") | local_name!("i") | local_name!("nobr") |
+        local_name!("s") | local_name!("small") | local_name!("strike") | local_name!("strong") |
+        local_name!("tt") | local_name!("u"))
+}
+
+/// unclosed lists the elements on the tree builder's stack of open elements that need an
+/// end tag, outermost first. html5ever doesn't expose the stack, only a trace of every
+/// node it holds on to: the document, the open elements, the formatting elements it
+/// remembers for reopening, then its head and form pointers. Formatting elements that
+/// were closed, like a `<b>` closed by `</p>`, stay on that list, so they have to be told
+/// apart from the open elements. Open formatting elements are traced twice, once on each
+/// list, and everything on the stack that isn't a formatting element is open, so the
+/// stack ends after the last of those. Formatting elements after that point that are
+/// only traced once are closed ones.
+fn unclosed<D: TreeSink>(builder: &TreeBuilder<D::Handle, D>) -> Vec<QualName> {
+    let handles = Handles(RefCell::new(Vec::new()));
+    builder.trace_handles(&handles);
+    let handles = handles.0.into_inner();
+    let name = |handle: &D::Handle| {
+        let name = builder.sink.elem_name(handle);
+        QualName::new(None, name.ns.clone(), name.local.clone())
+    };
+    // The document always comes first and isn't an element.
+    let mut names: Vec<QualName> = handles.iter().skip(1).map(&name).collect();
+    let mut handles = &handles[1.min(handles.len())..];
+    for pointer in &[local_name!("form"), local_name!("head")] {
+        if names.last().is_some_and(|name| name.ns == ns!(html) && name.local == *pointer) {
+            names.pop();
+            handles = &handles[..handles.len() - 1];
+        }
+    }
+    let traced_twice = |i: usize| {
+        handles.iter().enumerate()
+            .any(|(j, other)| i != j && builder.sink.same_node(&handles[i], other))
+    };
+    let first = |i: usize| !handles[..i].iter().any(|seen| builder.sink.same_node(seen, &handles[i]));
+    let stack_len = (0..handles.len()).rev()
+        .find(|&i| !is_formatting(&names[i]) || (first(i) && traced_twice(i)))
+        .map_or(0, |i| i + 1);
+    names.truncate(stack_len);
+    names.retain(|name| !optional_end(name));
+    names
+}
+
+/// TagBalance is the result of a `TagBalanceValidator`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagBalance {
+    /// The elements that were still open when the input ended, in document order.
+    pub unclosed: Vec<QualName>,
+}
+
+impl TagBalance {
+    /// is_balanced reports if every element that needs an end tag got one.
+    pub fn is_balanced(&self) -> bool {
+        self.unclosed.is_empty()
+    }
+}
+
+/// TagBalanceValidator parses a stream of bytes and resolves to a `TagBalance` listing the
+/// elements that were never closed, a quick way to spot truncated pages. No dom is built,
+/// only the names of the elements are kept around. Void elements like `<br>` never need
+/// closing and neither do elements whose end tag the spec lets you leave out, like `<p>`
+/// or `<li>`. An element the parser closed while recovering from an error, like a `<span>`
+/// closed by `</div>`, counts as closed, use `ParserFuture::with_diagnostics` to find
+/// those.
+#[must_use = "futures do nothing unless polled"]
+pub struct TagBalanceValidator<S> {
+    stream: S,
+    parser: Utf8LossyDecoder<Parser<NameSink>>,
+}
+
+impl<S, C, E> TagBalanceValidator<S>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    pub fn new(s: S) -> Self {
+        TagBalanceValidator {
+            stream: s,
+            parser: parse_document(NameSink::default(), Default::default()).from_utf8(),
+        }
+    }
+
+    fn report(&self) -> TagBalance {
+        // Whatever the tree builder still has open once all the input has been fed to it,
+        // but before it handles the end of the input, was never closed.
+        TagBalance { unclosed: unclosed(&self.parser.inner_sink.tokenizer.sink) }
+    }
+}
+
+impl<S, C, E> Future for TagBalanceValidator<S>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+{
+    type Item = TagBalance;
+    type Error = E;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            match self.stream.poll()? {
+                Async::Ready(Some(chunk)) => {
+                    self.parser.process(chunk.as_ref().into());
+                    if budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Async::Ready(None) => return Ok(Async::Ready(self.report())),
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+/// TruncationFuture parses a stream like `ParserFuture`, but fails with
+/// `Abort::Truncated` if the input ends while elements that need an end tag are still
+/// open. It is created by `ParserFuture::error_on_truncation`.
+#[must_use = "futures do nothing unless polled"]
+pub struct TruncationFuture<S, D>
+    where D: TreeSink,
+{
+    stream: S,
+    parser: Option<Utf8LossyDecoder<Parser<D>>>,
+}
+
+impl<S, D> TruncationFuture<S, D>
+    where D: TreeSink,
+{
+    /// unclosed lists the elements the tree builder still has open, the same way
+    /// `TagBalanceValidator` does.
+    fn unclosed(&self) -> Vec<QualName> {
+        unclosed(&self.parser.as_ref().expect("polled TruncationFuture after completion")
+            .inner_sink.tokenizer.sink)
+    }
+}
+
+impl<S, C, E, D> Future for TruncationFuture<S, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    type Item = D::Output;
+    type Error = Error<E>;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut budget = Budget::default();
+        loop {
+            match self.stream.poll().map_err(Error::Stream)? {
+                Async::Ready(Some(chunk)) => {
+                    self.parser.as_mut()
+                        .expect("polled TruncationFuture after completion")
+                        .process(chunk.as_ref().into());
+                    if budget.spend() {
+                        return Ok(Async::NotReady);
+                    }
+                },
+                Async::Ready(None) => {
+                    let unclosed = self.unclosed();
+                    let parser = self.parser.take().expect("polled TruncationFuture after completion");
+                    if !unclosed.is_empty() {
+                        return Err(Error::Aborted(Abort::Truncated(unclosed)));
+                    }
+                    return Ok(Async::Ready(parser.finish()));
+                },
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+
+impl<S, C, E, D> ParserFuture<S, C, E, D>
+    where S: Stream<Item=C, Error=E>,
+          C: AsRef<[u8]>,
+          D: TreeSink,
+{
+    /// error_on_truncation creates a `TruncationFuture`, which fails instead of resolving
+    /// when the input ends with elements still open, like a page whose connection dropped
+    /// halfway through. It uses the same rules as `TagBalanceValidator`, so pages that
+    /// leave out optional end tags like `</p>` or `</body>` are fine.
+    pub fn error_on_truncation(s: S, dom: D) -> TruncationFuture<S, D> {
+        TruncationFuture {
+            stream: s,
+            parser: Some(parse_document(dom, Default::default()).from_utf8()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{stream, Future};
+
+    use super::*;
+    use rcdom::RcDom;
+
+    fn validate(chunks: Vec<&'static str>) -> Vec<String> {
+        TagBalanceValidator::new(stream::iter_ok::<_, ()>(chunks)).wait().unwrap()
+            .unclosed.iter()
+            .map(|name| name.local.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_balanced() {
+        assert!(validate(vec!["<div><p>one<br><img src=x><li>two</d", "iv><svg><path/></svg>"])
+            .is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        // The `<b>` closed by `</p>` is reopened around the `<span>`, so it is open again.
+        assert_eq!(validate(vec!["<div><section><p><b>cut o", "ff</p></section><span>"]),
+                   vec!["div", "b", "span"]);
+    }
+
+    #[test]
+    fn test_closed_by_recovery() {
+        assert!(validate(vec!["<p><b>x</p><p>y</p>"]).is_empty());
+        assert!(validate(vec!["<div><b>a</div>"]).is_empty());
+        assert_eq!(validate(vec!["<b><b><b><b>x"]), vec!["b", "b", "b", "b"]);
+    }
+
+    #[test]
+    fn test_error_on_truncation() {
+        let parse = |chunks: Vec<&'static str>| {
+            ParserFuture::error_on_truncation(stream::iter_ok::<_, ()>(chunks), RcDom::default()).wait()
+        };
+        assert!(parse(vec!["<ul><li>one<li>two</u", "l><p>done"]).is_ok());
+        assert!(parse(vec!["<p><b>bold</p><p>rest</p>"]).is_ok());
+        match parse(vec!["<div><span>cut", " off"]) {
+            Err(Error::Aborted(Abort::Truncated(ref unclosed))) => {
+                let names: Vec<&str> = unclosed.iter().map(|name| &*name.local).collect();
+                assert_eq!(names, vec!["div", "span"]);
+            },
+            _ => panic!("expected truncation"),
+        }
+    }
+}